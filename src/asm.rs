@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::opcode::Instruction;
+
+
+//Roms are loaded, and so assembled, at 0x200
+const ORIGIN: u32 = 0x200;
+
+const MNEMONICS: [&str; 32] = [
+    "NOP", "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE", "SAVE", "LOAD",
+    "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH"
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError{
+    pub line: usize,
+    pub column: usize,
+    pub message: String
+}
+
+impl fmt::Display for AsmError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+//A word of source text and where it starts, columns count from 1
+#[derive(Clone, Copy)]
+struct Token<'a>{
+    text: &'a str,
+    line: usize,
+    column: usize
+}
+
+impl<'a> Token<'a>{
+    fn error(& self, message :String) -> AsmError{
+        AsmError { line: self.line, column: self.column, message }
+    }
+}
+
+//An instruction or data directive waiting for its operands to be resolved
+struct Statement<'a>{
+    mnemonic: Token<'a>,
+    operands: Vec<Token<'a>>
+}
+
+enum Operand{
+    Register(usize),
+    Number(i64),
+    Index,
+    IndexIndirect,
+    Long(i64),
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags
+}
+
+//Trims a slice of line while keeping track of its column
+fn trimmed<'a>(text :&'a str, line :usize, column :usize) -> Token<'a>{
+    let leading = text.len() - text.trim_start().len();
+    Token { text: text.trim(), line, column: column + leading }
+}
+
+fn is_identifier(text :&str) -> bool{
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_literal(text :&str) -> Option<i64>{
+    let (negative, digits) = match text.strip_prefix('-'){
+        Some(rest) => (true, rest),
+        None => (false, text)
+    };
+    let lower = digits.to_lowercase();
+    let value = if let Some(hex) = lower.strip_prefix("0x"){
+        i64::from_str_radix(hex, 16).ok()?
+    }
+    else if let Some(binary) = lower.strip_prefix("0b"){
+        i64::from_str_radix(binary, 2).ok()?
+    }
+    else{
+        lower.parse().ok()?
+    };
+    Some(if negative {-value} else {value})
+}
+
+//A number literal, label or define
+fn parse_value(token :&Token, symbols :&HashMap<String, i64>) -> Result<i64, AsmError>{
+    if let Some(value) = parse_literal(token.text){
+        return Ok(value);
+    }
+    if is_identifier(token.text){
+        return symbols.get(token.text).copied().ok_or(token.error(format!("Undefined symbol '{}'", token.text)));
+    }
+    Err(token.error(format!("Invalid operand '{}'", token.text)))
+}
+
+fn parse_register(text :&str) -> Option<usize>{
+    let digits = text.strip_prefix('V').or(text.strip_prefix('v'))?;
+    if digits.len() != 1{
+        return None;
+    }
+    usize::from_str_radix(digits, 16).ok()
+}
+
+fn parse_operand(token :&Token, symbols :&HashMap<String, i64>) -> Result<Operand, AsmError>{
+    if let Some(register) = parse_register(token.text){
+        return Ok(Operand::Register(register));
+    }
+
+    if let Some(address) = long_address(token){
+        return Ok(Operand::Long(parse_value(&address, symbols)?));
+    }
+
+    let upper = token.text.to_uppercase();
+    let operand = match upper.as_str(){
+        "I" => Operand::Index,
+        "[I]" => Operand::IndexIndirect,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "HF" => Operand::BigFont,
+        "B" => Operand::Bcd,
+        "R" => Operand::Flags,
+        _ => Operand::Number(parse_value(token, symbols)?)
+    };
+    Ok(operand)
+}
+
+//The address in a "LONG address" operand
+fn long_address<'a>(token :&Token<'a>) -> Option<Token<'a>>{
+    let (keyword, address) = token.text.split_once(char::is_whitespace)?;
+    if !keyword.eq_ignore_ascii_case("LONG"){
+        return None;
+    }
+    Some(trimmed(address, token.line, token.column + keyword.len() + 1))
+}
+
+//Range checks a number operand
+fn number(token :&Token, value :i64, min :i64, max :i64, what :&str) -> Result<i64, AsmError>{
+    if value < min || value > max{
+        return Err(token.error(format!("{} {} is out of range ({}..={})", what, value, min, max)));
+    }
+    Ok(value)
+}
+
+//Bytes can be written signed, e.g. ADD V1, -1
+fn byte(token :&Token, value :i64) -> Result<u8, AsmError>{
+    Ok(number(token, value, -128, 255, "Byte")? as u8)
+}
+
+fn address(token :&Token, value :i64) -> Result<u16, AsmError>{
+    Ok(number(token, value, 0, 0xFFF, "Address")? as u16)
+}
+
+fn nibble(token :&Token, value :i64) -> Result<u8, AsmError>{
+    Ok(number(token, value, 0, 0xF, "Value")? as u8)
+}
+
+//Size in bytes of a statement, known before labels are resolved
+fn statement_size(statement :&Statement) -> Result<usize, AsmError>{
+    let mnemonic = statement.mnemonic.text.to_uppercase();
+    let size = match mnemonic.as_str(){
+        "DB" => statement.operands.len(),
+        "DW" => 2*statement.operands.len(),
+        "SPRITE" => sprite_bytes(statement)?.len(),
+        "LD" if statement.operands.len() == 2 && long_address(&statement.operands[1]).is_some() => 4,
+        _ => 2
+    };
+    Ok(size)
+}
+
+//SPRITE ..####.. is one byte per 8 pixels, '#', 'X' or '1' set and '.', '_' or '0' clear
+fn sprite_bytes(statement :&Statement) -> Result<Vec<u8>, AsmError>{
+    let token = statement.operands.first().ok_or(statement.mnemonic.error(String::from("SPRITE expects a row of pixels")))?;
+    if statement.operands.len() > 1 || token.text.is_empty() || token.text.len() % 8 != 0{
+        return Err(token.error(String::from("Sprite rows must be 8 or 16 pixels wide")));
+    }
+
+    let mut bytes = vec![0u8; token.text.len()/8];
+    for (i, pixel) in token.text.chars().enumerate(){
+        match pixel{
+            '#' | 'X' | 'x' | '1' => bytes[i/8] |= 0x80 >> (i%8),
+            '.' | '_' | '0' => (),
+            other => return Err(AsmError { line: token.line, column: token.column + i, message: format!("Invalid sprite pixel '{}'", other) })
+        }
+    }
+    Ok(bytes)
+}
+
+fn encode_statement(statement :&Statement, symbols :&HashMap<String, i64>) -> Result<Vec<u8>, AsmError>{
+    let mnemonic = statement.mnemonic.text.to_uppercase();
+    let tokens = &statement.operands;
+
+    //Data directives
+    match mnemonic.as_str(){
+        "DB" => return tokens.iter().map(|token| byte(token, parse_value(token, symbols)?)).collect(),
+        "DW" => {
+            let mut bytes = vec![];
+            for token in tokens{
+                let word = number(token, parse_value(token, symbols)?, -0x8000, 0xFFFF, "Word")? as u16;
+                bytes.extend_from_slice(&word.to_be_bytes());
+            }
+            return Ok(bytes);
+        },
+        "SPRITE" => return sprite_bytes(statement),
+        _ => ()
+    }
+
+    let operands = tokens.iter().map(|token| parse_operand(token, symbols)).collect::<Result<Vec<Operand>, AsmError>>()?;
+
+    let instruction = match (mnemonic.as_str(), operands.as_slice()){
+        ("NOP", []) => Instruction::Nop,
+        ("CLS", []) => Instruction::ClearScreen,
+        ("RET", []) => Instruction::Return,
+        ("SCD", [Operand::Number(n)]) => Instruction::ScrollDown { n: nibble(&tokens[0], *n)? },
+        ("SCU", [Operand::Number(n)]) => Instruction::ScrollUp { n: nibble(&tokens[0], *n)? },
+        ("SCR", []) => Instruction::ScrollRight,
+        ("SCL", []) => Instruction::ScrollLeft,
+        ("EXIT", []) => Instruction::Exit,
+        ("LOW", []) => Instruction::LowRes,
+        ("HIGH", []) => Instruction::HighRes,
+        ("JP", [Operand::Number(nnn)]) => Instruction::Jump { nnn: address(&tokens[0], *nnn)? },
+        //BXNN: the register is the high digit of the address, so JP V3 takes 0x3NN or
+        //just NN. JP V0 takes any address, as in the original BNNN
+        ("JP", [Operand::Register(x), Operand::Number(nnn)]) => {
+            let mut nnn = address(&tokens[1], *nnn)?;
+            if *x != 0 && nnn <= 0xFF{
+                nnn |= (*x as u16) << 8;
+            }
+            if *x != 0 && (nnn >> 8) as usize != *x{
+                return Err(tokens[1].error(format!("JP V{:X} jumps within 0x{:X}00-0x{:X}FF, 0x{:03X} is outside", x, x, x, nnn)));
+            }
+            Instruction::JumpOffset { x: (nnn >> 8) as usize, nnn }
+        },
+        ("CALL", [Operand::Number(nnn)]) => Instruction::Call { nnn: address(&tokens[0], *nnn)? },
+        ("SE", [Operand::Register(x), Operand::Number(nn)]) => Instruction::SkipEqualImmediate { x: *x, nn: byte(&tokens[1], *nn)? },
+        ("SE", [Operand::Register(x), Operand::Register(y)]) => Instruction::SkipEqual { x: *x, y: *y },
+        ("SNE", [Operand::Register(x), Operand::Number(nn)]) => Instruction::SkipNotEqualImmediate { x: *x, nn: byte(&tokens[1], *nn)? },
+        ("SNE", [Operand::Register(x), Operand::Register(y)]) => Instruction::SkipNotEqual { x: *x, y: *y },
+        ("SAVE", [Operand::Register(x), Operand::Register(y)]) => Instruction::SaveRange { x: *x, y: *y },
+        ("LOAD", [Operand::Register(x), Operand::Register(y)]) => Instruction::LoadRange { x: *x, y: *y },
+        ("LD", [Operand::Register(x), Operand::Number(nn)]) => Instruction::LoadImmediate { x: *x, nn: byte(&tokens[1], *nn)? },
+        ("LD", [Operand::Register(x), Operand::Register(y)]) => Instruction::Move { x: *x, y: *y },
+        ("LD", [Operand::Index, Operand::Number(nnn)]) => Instruction::LoadIndex { nnn: address(&tokens[1], *nnn)? },
+        ("LD", [Operand::Index, Operand::Long(nnnn)]) => {
+            let nnnn = number(&tokens[1], *nnnn, 0, 0xFFFF, "Address")? as u16;
+            let mut bytes = Instruction::LoadIndexLong.encode().to_be_bytes().to_vec();
+            bytes.extend_from_slice(&nnnn.to_be_bytes());
+            return Ok(bytes);
+        },
+        ("LD", [Operand::Register(x), Operand::DelayTimer]) => Instruction::GetDelay { x: *x },
+        ("LD", [Operand::Register(x), Operand::Key]) => Instruction::WaitKey { x: *x },
+        ("LD", [Operand::DelayTimer, Operand::Register(x)]) => Instruction::SetDelay { x: *x },
+        ("LD", [Operand::SoundTimer, Operand::Register(x)]) => Instruction::SetSound { x: *x },
+        ("LD", [Operand::Font, Operand::Register(x)]) => Instruction::FontChar { x: *x },
+        ("LD", [Operand::BigFont, Operand::Register(x)]) => Instruction::BigFontChar { x: *x },
+        ("LD", [Operand::Bcd, Operand::Register(x)]) => Instruction::Bcd { x: *x },
+        ("LD", [Operand::IndexIndirect, Operand::Register(x)]) => Instruction::Store { x: *x },
+        ("LD", [Operand::Register(x), Operand::IndexIndirect]) => Instruction::Load { x: *x },
+        ("LD", [Operand::Flags, Operand::Register(x)]) => Instruction::SaveFlags { x: *x },
+        ("LD", [Operand::Register(x), Operand::Flags]) => Instruction::LoadFlags { x: *x },
+        ("ADD", [Operand::Register(x), Operand::Number(nn)]) => Instruction::AddImmediate { x: *x, nn: byte(&tokens[1], *nn)? },
+        ("ADD", [Operand::Register(x), Operand::Register(y)]) => Instruction::Add { x: *x, y: *y },
+        ("ADD", [Operand::Index, Operand::Register(x)]) => Instruction::AddIndex { x: *x },
+        ("OR", [Operand::Register(x), Operand::Register(y)]) => Instruction::Or { x: *x, y: *y },
+        ("AND", [Operand::Register(x), Operand::Register(y)]) => Instruction::And { x: *x, y: *y },
+        ("XOR", [Operand::Register(x), Operand::Register(y)]) => Instruction::Xor { x: *x, y: *y },
+        ("SUB", [Operand::Register(x), Operand::Register(y)]) => Instruction::Sub { x: *x, y: *y },
+        ("SUBN", [Operand::Register(x), Operand::Register(y)]) => Instruction::SubReverse { x: *x, y: *y },
+        ("SHR", [Operand::Register(x)]) => Instruction::ShiftRight { x: *x, y: *x },
+        ("SHR", [Operand::Register(x), Operand::Register(y)]) => Instruction::ShiftRight { x: *x, y: *y },
+        ("SHL", [Operand::Register(x)]) => Instruction::ShiftLeft { x: *x, y: *x },
+        ("SHL", [Operand::Register(x), Operand::Register(y)]) => Instruction::ShiftLeft { x: *x, y: *y },
+        ("RND", [Operand::Register(x), Operand::Number(nn)]) => Instruction::Random { x: *x, nn: byte(&tokens[1], *nn)? },
+        ("DRW", [Operand::Register(x), Operand::Register(y), Operand::Number(n)]) => Instruction::Draw { x: *x, y: *y, n: nibble(&tokens[2], *n)? },
+        ("SKP", [Operand::Register(x)]) => Instruction::SkipKeyPressed { x: *x },
+        ("SKNP", [Operand::Register(x)]) => Instruction::SkipKeyNotPressed { x: *x },
+        //Only masks 0-3 mean anything, but any nibble is accepted since FN01 decodes for every N
+        ("PLANE", [Operand::Number(n)]) => Instruction::SelectPlanes { n: number(&tokens[0], *n, 0, 15, "Plane mask")? as u8 },
+        ("AUDIO", []) => Instruction::LoadAudio,
+        ("PITCH", [Operand::Register(x)]) => Instruction::SetPitch { x: *x },
+        (known, _) if MNEMONICS.contains(&known) => return Err(statement.mnemonic.error(format!("Invalid operands for {}", mnemonic))),
+        _ => return Err(statement.mnemonic.error(format!("Unknown mnemonic '{}'", statement.mnemonic.text)))
+    };
+
+    Ok(instruction.encode().to_be_bytes().to_vec())
+}
+
+//Assembles source text into a rom image to be loaded at 0x200.
+//One statement per line, ';' starts a comment:
+//  label:                  defines a label at the current address
+//  define NAME value       defines a constant
+//  DB 1, 0x2, 0b11         data bytes, DW for 16 bit words
+//  SPRITE ..####..         one sprite row, 8 or 16 pixels
+//  LD V1, 0x2A             instructions, using the same mnemonics as the disassembler
+pub fn assemble(source :&str) -> Result<Vec<u8>, AsmError>{
+    let mut symbols :HashMap<String, i64> = HashMap::new();
+    let mut statements :Vec<Statement> = vec![];
+    let mut address = ORIGIN as usize;
+
+    //Pass 1: collect labels and defines, and lay out statements
+    for (line_index, raw_line) in source.lines().enumerate(){
+        let line_num = line_index + 1;
+        let code = raw_line.split(';').next().unwrap_or("");
+        let mut rest = trimmed(code, line_num, 1);
+        if rest.text.is_empty(){
+            continue;
+        }
+
+        if let Some(colon) = rest.text.find(':'){
+            let label = trimmed(&rest.text[..colon], line_num, rest.column);
+            if !is_identifier(label.text){
+                return Err(label.error(format!("Invalid label '{}'", label.text)));
+            }
+            if symbols.insert(label.text.to_string(), address as i64).is_some(){
+                return Err(label.error(format!("Duplicate symbol '{}'", label.text)));
+            }
+            rest = trimmed(&rest.text[colon+1..], line_num, rest.column + colon + 1);
+            if rest.text.is_empty(){
+                continue;
+            }
+        }
+
+        let mnemonic_end = rest.text.find(char::is_whitespace).unwrap_or(rest.text.len());
+        let mnemonic = Token { text: &rest.text[..mnemonic_end], line: line_num, column: rest.column };
+
+        let mut operands = vec![];
+        let operand_text = &rest.text[mnemonic_end..];
+        if !operand_text.trim().is_empty(){
+            let mut column = rest.column + mnemonic_end;
+            for part in operand_text.split(','){
+                let operand = trimmed(part, line_num, column);
+                if operand.text.is_empty(){
+                    return Err(operand.error(String::from("Missing operand")));
+                }
+                operands.push(operand);
+                column += part.len() + 1;
+            }
+        }
+
+        if mnemonic.text.eq_ignore_ascii_case("define"){
+            if operands.len() != 1{
+                return Err(mnemonic.error(String::from("Expected 'define NAME value'")));
+            }
+            let (name_text, value_text) = operands[0].text.split_once(char::is_whitespace)
+                .ok_or(operands[0].error(format!("Missing value for '{}'", operands[0].text)))?;
+            let name = Token { text: name_text, line: line_num, column: operands[0].column };
+            let value = trimmed(value_text, line_num, name.column + name_text.len() + 1);
+            if !is_identifier(name.text){
+                return Err(name.error(format!("Invalid define name '{}'", name.text)));
+            }
+            let resolved = parse_value(&value, &symbols)?;
+            if symbols.insert(name.text.to_string(), resolved).is_some(){
+                return Err(name.error(format!("Duplicate symbol '{}'", name.text)));
+            }
+            continue;
+        }
+
+        let statement = Statement { mnemonic, operands };
+        address += statement_size(&statement)?;
+        statements.push(statement);
+    }
+
+    //Pass 2: encode now that every label is known
+    let mut rom = vec![];
+    for statement in &statements{
+        rom.extend(encode_statement(statement, &symbols)?);
+    }
+
+    let capacity = 0x10000 - ORIGIN as usize;
+    if rom.len() > capacity{
+        return Err(AsmError { line: source.lines().count(), column: 1, message: format!("Program is {} bytes but only {} bytes fit in memory", rom.len(), capacity) });
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::disasm;
+
+    //Every word, valid or not, has to survive a trip through a listing. The trailing
+    //word gives F000 its long operand
+    #[test]
+    fn reassembles_every_disassembled_word(){
+        for word in 0..=0xFFFFu16{
+            let rom = [(word >> 8) as u8, word as u8, 0x12, 0x34];
+            let listing = disasm::disassemble(&rom, 0x200);
+            let assembled = assemble(&listing).unwrap_or_else(|e| panic!("{:04X}: {}\n{}", word, e, listing));
+            assert_eq!(assembled, rom, "{:04X}:\n{}", word, listing);
+        }
+    }
+
+    #[test]
+    fn jumps_with_offset_name_their_register(){
+        assert_eq!(assemble("JP V0, 0x2EA").unwrap(), [0xB2, 0xEA]);
+        assert_eq!(assemble("JP V3, 0x345").unwrap(), [0xB3, 0x45]);
+        assert_eq!(assemble("JP V3, 0x45").unwrap(), [0xB3, 0x45]);
+        assert!(assemble("JP V3, 0x456").is_err());
+        assert!(disasm::disassemble(&[0xB3, 0x45], 0x200).contains("JP V3, 0x345"));
+    }
+
+    #[test]
+    fn rejects_out_of_range_operands(){
+        assert!(assemble("LD V0, 256").is_err());
+        assert!(assemble("PLANE 16").is_err());
+        assert!(assemble("DRW V0, V1, 16").is_err());
+    }
+}
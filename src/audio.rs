@@ -0,0 +1,178 @@
+use std::f32::consts::PI;
+use std::io::{self, Write};
+use crate::Chip8;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform{
+    Square,
+    Sine,
+    Triangle
+}
+
+impl Waveform{
+
+    pub fn from_name(name :&str) -> Option<Waveform>{
+        match name.to_lowercase().as_str(){
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            _ => None
+        }
+    }
+
+    //Sample between -1 and 1 at phase 0..1 of a period
+    fn sample(& self, phase :f32) -> f32{
+        match self{
+            Waveform::Square => if phase < 0.5 {1.0} else {-1.0},
+            Waveform::Sine => (2.0*PI*phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0*(phase - 0.5).abs()
+        }
+    }
+}
+
+//How the beep sounds. Volume goes from 0 to 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioSettings{
+    pub waveform: Waveform,
+    pub frequency: f32,
+    pub volume: f32,
+    pub muted: bool
+}
+
+impl Default for AudioSettings{
+    fn default() -> AudioSettings{
+        AudioSettings { waveform: Waveform::Square, frequency: 440.0, volume: 0.25, muted: false }
+    }
+}
+
+//Where the sound timer ends up. Frontends play it, the headless runner records it
+pub trait AudioOutput{
+    //Called once per 60Hz frame, before the timers are decremented
+    fn update(&mut self, emulator :&Chip8);
+    fn set_muted(&mut self, muted :bool);
+    fn is_muted(& self) -> bool;
+}
+
+//Generates samples for the beep, or for the XO-CHIP audio pattern once the rom
+//has loaded one. Kept separate from the audio device so any backend can drive it
+pub struct Synth{
+    settings: AudioSettings,
+    sample_rate: f32,
+    pattern: Option<[u8; 16]>,
+    playback_rate: f32,
+    //Position in the current period for the beep, or bit index into the pattern
+    position: f32,
+    playing: bool
+}
+
+impl Synth{
+
+    pub fn new(settings :AudioSettings, sample_rate :f32) -> Synth{
+        Synth { settings, sample_rate, pattern: None, playback_rate: 4000.0, position: 0.0, playing: false }
+    }
+
+    pub fn set_muted(&mut self, muted :bool){
+        self.settings.muted = muted;
+    }
+
+    //Copies the sound state of the emulator
+    pub fn update(&mut self, emulator :&Chip8){
+        let pattern = emulator.get_audio_pattern().copied();
+        if pattern.is_some() != self.pattern.is_some(){
+            self.position = 0.0;
+        }
+        self.pattern = pattern;
+        self.playback_rate = emulator.get_audio_playback_rate();
+        self.playing = emulator.get_sound_timer() > 0;
+    }
+
+    pub fn fill(&mut self, out :&mut [f32]){
+        for sample in out.iter_mut(){
+            if !self.playing || self.settings.muted{
+                *sample = 0.0;
+                continue;
+            }
+
+            match self.pattern{
+                Some(pattern) => {
+                    let bit_index = self.position as usize;
+                    let bit = (pattern[bit_index/8] >> (7 - (bit_index%8))) & 1;
+                    *sample = if bit == 1 {self.settings.volume} else {-self.settings.volume};
+                    self.position = (self.position + self.playback_rate/self.sample_rate) % 128.0;
+                },
+                None => {
+                    *sample = self.settings.waveform.sample(self.position) * self.settings.volume;
+                    self.position = (self.position + self.settings.frequency/self.sample_rate) % 1.0;
+                }
+            }
+        }
+    }
+}
+
+impl AudioOutput for Synth{
+
+    fn update(&mut self, emulator :&Chip8){
+        Synth::update(self, emulator);
+    }
+
+    fn set_muted(&mut self, muted :bool){
+        Synth::set_muted(self, muted);
+    }
+
+    fn is_muted(& self) -> bool{
+        self.settings.muted
+    }
+}
+
+//Records which frames the beep was on instead of playing it
+#[derive(Default)]
+pub struct BeepRecorder{
+    envelope: Vec<bool>,
+    muted: bool
+}
+
+impl BeepRecorder{
+
+    pub fn new() -> BeepRecorder{
+        BeepRecorder::default()
+    }
+
+    //(first frame, number of frames) of every beep
+    pub fn get_beeps(& self) -> Vec<(usize, usize)>{
+        let mut beeps :Vec<(usize, usize)> = vec![];
+        for (frame, on) in self.envelope.iter().enumerate(){
+            if !on{
+                continue;
+            }
+            match beeps.last_mut(){
+                Some((start, length)) if *start + *length == frame => *length += 1,
+                _ => beeps.push((frame, 1))
+            }
+        }
+        beeps
+    }
+
+    //Writes one "<first frame> <number of frames>" line per beep
+    pub fn write_log<W: Write>(& self, writer :&mut W) -> io::Result<()>{
+        for (start, length) in self.get_beeps(){
+            writeln!(writer, "{} {}", start, length)?;
+        }
+        Ok(())
+    }
+}
+
+impl AudioOutput for BeepRecorder{
+
+    fn update(&mut self, emulator :&Chip8){
+        self.envelope.push(!self.muted && emulator.get_sound_timer() > 0);
+    }
+
+    fn set_muted(&mut self, muted :bool){
+        self.muted = muted;
+    }
+
+    fn is_muted(& self) -> bool{
+        self.muted
+    }
+}
@@ -0,0 +1,267 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use crate::Chip8;
+use crate::palette::Palette;
+use crate::renderer::{Frame, PngRenderer, Renderer};
+
+
+//Writes the display to a PNG at scale x scale image pixels per CHIP-8 pixel
+pub fn screenshot(emulator :&Chip8, path :&str, palette :Palette, scale :u32) -> Result<(), String>{
+    if let Some(directory) = Path::new(path).parent().filter(|directory| !directory.as_os_str().is_empty()){
+        std::fs::create_dir_all(directory).map_err(|e| format!("Can't create {}: {}", directory.display(), e))?;
+    }
+    PngRenderer::new(String::from(path), palette, scale).present(&Frame::of(emulator))
+}
+
+//Screenshot hotkey: writes <prefix>-<n>.png with the first n that isn't taken and returns the path
+pub fn numbered_screenshot(emulator :&Chip8, prefix :&str, palette :Palette, scale :u32) -> Result<String, String>{
+    let path = (1..).map(|n| format!("{}-{}.png", prefix, n)).find(|path| !Path::new(path).exists()).unwrap();
+    screenshot(emulator, &path, palette, scale)?;
+    Ok(path)
+}
+
+enum Output{
+    Gif{
+        encoder: gif::Encoder<BufWriter<File>>,
+        //Palette of the first frame, frames in other colors carry their own
+        first: Palette,
+        //Frame waiting to be written with the 60Hz frame it was first shown at
+        pending: Option<(Vec<u8>, Palette, u32)>
+    },
+    //Frames are written to the encoder's stdin
+    Pipe{
+        encoder: Child
+    }
+}
+
+//Records every 60Hz frame to an animated GIF or as raw RGB24 frames piped into an
+//encoder command. The size is fixed for the whole recording at 128x64 high-res pixels
+//of scale/2, which is the window size for even scales, low-res frames are doubled.
+//An error stops the recording but not the game, finish reports it
+pub struct VideoRecorder{
+    target: String,
+    output: Output,
+    palette: Palette,
+    width: usize,
+    height: usize,
+    frames: u32,
+    error: Option<String>
+}
+
+impl VideoRecorder{
+
+    //target is a path ending in .gif or '|' and a shell command that reads raw
+    //frames from stdin, where {size} is replaced with the frame size as WxH
+    pub fn create(target :&str, palette :Palette, scale :u32) -> Result<VideoRecorder, String>{
+        let pixel = (scale/2).max(1) as usize;
+        let (width, height) = (128*pixel, 64*pixel);
+        let output = if let Some(command) = target.strip_prefix('|'){
+            let command = command.trim().replace("{size}", &format!("{}x{}", width, height));
+            let shell = if cfg!(windows) {["cmd", "/C"]} else {["sh", "-c"]};
+            let encoder = Command::new(shell[0]).args([shell[1], &command]).stdin(Stdio::piped()).spawn()
+                .map_err(|e| format!("Can't start '{}': {}", command, e))?;
+            Output::Pipe { encoder }
+        }
+        else if target.ends_with(".gif"){
+            if width > u16::MAX as usize{
+                return Err(format!("{}x{} is too big for a GIF, use a smaller --scale", width, height));
+            }
+            let error = |e :&dyn std::fmt::Display| format!("Can't write {}: {}", target, e);
+            let file = File::create(target).map_err(|e| error(&e))?;
+            let mut encoder = gif::Encoder::new(BufWriter::new(file), width as u16, height as u16, palette.colors.as_flattened()).map_err(|e| error(&e))?;
+            encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| error(&e))?;
+            Output::Gif { encoder, first: palette, pending: None }
+        }
+        else{
+            return Err(format!("Can't record to '{}', expected a .gif path or '|<encoder command>'", target));
+        };
+        Ok(VideoRecorder { target: String::from(target), output, palette, width, height, frames: 0, error: None })
+    }
+
+    //Colors for the following frames, e.g. after the palette hotkey
+    pub fn set_palette(&mut self, palette :Palette){
+        self.palette = palette;
+    }
+
+    //Adds the display at the end of a 60Hz frame, whether or not it changed
+    pub fn record(&mut self, frame :&Frame){
+        if self.error.is_some(){
+            return;
+        }
+        if let Err(e) = self.write(frame){
+            self.error = Some(format!("Can't record to {}: {}", self.target, e));
+        }
+        self.frames += 1;
+    }
+
+    fn write(&mut self, frame :&Frame) -> Result<(), String>{
+        let pixels = self.scale(frame);
+        let (palette, width, height, index) = (self.palette, self.width, self.height, self.frames);
+        match &mut self.output{
+            Output::Gif { encoder, first, pending } => {
+                match pending{
+                    //Unchanged frames just make the pending one last longer
+                    Some((last, last_palette, _)) if *last == pixels && *last_palette == palette => (),
+                    //Most viewers slow frames shorter than 2/100s down to 1/10s, so a
+                    //frame that would be that short is replaced by the next one instead
+                    Some((last, last_palette, start)) if centiseconds(index) - centiseconds(*start) < 2 => {
+                        *last = pixels;
+                        *last_palette = palette;
+                    },
+                    _ => {
+                        if let Some(last) = pending.take(){
+                            write_gif_frame(encoder, *first, last, index, width, height).map_err(|e| e.to_string())?;
+                        }
+                        *pending = Some((pixels, palette, index));
+                    }
+                }
+            },
+            Output::Pipe { encoder } => {
+                let rgb :Vec<u8> = pixels.iter().flat_map(|pixel| palette.color(*pixel)).collect();
+                let stdin = encoder.stdin.as_mut().ok_or("the encoder's input is closed")?;
+                stdin.write_all(&rgb).map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    //Writes the last frame and waits for the encoder, returns a summary for the player
+    pub fn finish(self) -> Result<String, String>{
+        let error = |e :&dyn std::fmt::Display| format!("Can't record to {}: {}", self.target, e);
+        match self.output{
+            Output::Gif { mut encoder, first, pending } => {
+                if let Some(last) = pending.filter(|_| self.error.is_none()){
+                    write_gif_frame(&mut encoder, first, last, self.frames, self.width, self.height).map_err(|e| error(&e))?;
+                }
+                encoder.into_inner().and_then(|mut writer| writer.flush()).map_err(|e| error(&e))?;
+            },
+            Output::Pipe { mut encoder } => {
+                //Waiting closes stdin first, which tells the encoder the video has ended
+                let status = encoder.wait().map_err(|e| error(&e))?;
+                if !status.success(){
+                    return Err(self.error.unwrap_or(format!("Encoder '{}' failed: {}", self.target, status)));
+                }
+            }
+        }
+        if let Some(error) = self.error{
+            return Err(error);
+        }
+        Ok(format!("Recorded {} frames ({:.1}s) to {}", self.frames, self.frames as f32/60.0, self.target))
+    }
+
+    //Palette indices at the recording size
+    fn scale(& self, frame :&Frame) -> Vec<u8>{
+        let scale = (self.width/frame.width).max(1);
+        let mut pixels = Vec::with_capacity(self.width*self.height);
+        for row in frame.pixels.chunks(frame.width){
+            let line :Vec<u8> = row.iter().flat_map(|pixel| [pixel & 0x3].repeat(scale)).collect();
+            for _i in 0..scale{
+                pixels.extend_from_slice(&line);
+            }
+        }
+        pixels.resize(self.width*self.height, 0);
+        pixels
+    }
+}
+
+//Time of a 60Hz frame boundary in the 1/100s steps GIF delays are counted in
+fn centiseconds(frame :u32) -> u32{
+    (frame*100 + 30)/60
+}
+
+fn write_gif_frame(encoder :&mut gif::Encoder<BufWriter<File>>, first :Palette, (pixels, palette, start) :(Vec<u8>, Palette, u32), end :u32, width :usize, height :usize) -> Result<(), gif::EncodingError>{
+    let frame = gif::Frame {
+        width: width as u16,
+        height: height as u16,
+        delay: (centiseconds(end) - centiseconds(start)).min(u16::MAX as u32) as u16,
+        palette: (palette != first).then(|| palette.colors.as_flattened().to_vec()),
+        buffer: Cow::Owned(pixels),
+        ..gif::Frame::default()
+    };
+    encoder.write_frame(&frame)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn temp_path(name :&str) -> String{
+        std::env::temp_dir().join(format!("rust8-capture-{}-{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    //Low-res frame with only the pixel at col lit
+    fn frame(pixels :&mut [u8; 64*32], col :usize) -> Frame<'_>{
+        pixels.fill(0);
+        pixels[col] = 1;
+        Frame { pixels, width: 64, height: 32 }
+    }
+
+    #[test]
+    fn gif_frames_last_until_the_display_changes(){
+        let path = temp_path("delays.gif");
+        let mut recorder = VideoRecorder::create(&path, Palette::default(), 2).unwrap();
+        let mut pixels = [0; 64*32];
+        //One frame of 0, one of 1 which is too short to keep, then 8 of 2
+        recorder.record(&frame(&mut pixels, 0));
+        recorder.record(&frame(&mut pixels, 1));
+        for _frame in 0..8{
+            recorder.record(&frame(&mut pixels, 2));
+        }
+        assert!(recorder.finish().unwrap().starts_with("Recorded 10 frames"));
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (128, 64));
+        let mut frames = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap(){
+            let lit :Vec<usize> = frame.buffer.iter().enumerate().filter(|(_, pixel)| **pixel != 0).map(|(i, _)| i).collect();
+            frames.push((frame.delay, lit));
+        }
+        //10 frames at 60Hz are 17/100s, the second frame's time goes to the third
+        assert_eq!(frames, [(2, vec![0, 1, 128, 129]), (15, vec![4, 5, 132, 133])]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn frames_are_scaled_to_the_recording_size(){
+        let path = temp_path("scale.gif");
+        let recorder = VideoRecorder::create(&path, Palette::default(), 4).unwrap();
+        let mut pixels = [0; 64*32];
+        //Low-res pixels are 4x4 at scale 4, high-res ones 2x2
+        let low = recorder.scale(&frame(&mut pixels, 1));
+        assert_eq!(low.len(), 256*128);
+        assert_eq!(low.iter().filter(|pixel| **pixel != 0).count(), 16);
+        assert!((4..8).all(|col| (0..4).all(|row| low[col + 256*row] == 1)));
+        let mut high = [0; 128*64];
+        high[128*63 + 127] = 3;
+        let high = recorder.scale(&Frame { pixels: &high, width: 128, height: 64 });
+        assert_eq!(high.iter().filter(|pixel| **pixel != 0).count(), 4);
+        assert_eq!(high[256*128 - 1], 3);
+        recorder.finish().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn piped_commands_get_the_frame_size(){
+        let prefix = temp_path("pipe");
+        let mut recorder = VideoRecorder::create(&format!("| cat > {}-{{size}}.rgb", prefix), Palette::default(), 2).unwrap();
+        let mut pixels = [0; 64*32];
+        recorder.record(&frame(&mut pixels, 0));
+        recorder.record(&frame(&mut pixels, 0));
+        recorder.finish().unwrap();
+        let raw = std::fs::read(format!("{}-128x64.rgb", prefix)).unwrap();
+        assert_eq!(raw.len(), 2*128*64*3);
+        assert_eq!(raw[..3], Palette::default().color(1));
+        std::fs::remove_file(format!("{}-128x64.rgb", prefix)).unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_targets(){
+        assert!(VideoRecorder::create("clip.mp4", Palette::default(), 2).is_err());
+    }
+}
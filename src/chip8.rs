@@ -1,32 +1,103 @@
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::ops::Range;
 use rand::random;
-
-pub enum Key{
-    Pressed,
-    Released
-}
+use crate::rng::{Rng, XorShift};
+use crate::error::Chip8Error;
+use crate::opcode::Instruction;
+use crate::quirks::Quirks;
 
 pub struct Chip8 {
-    memory: [u8; 4096],
+    //64KiB for XO-CHIP, classic roms only use the first 4KiB
+    memory: Vec<u8>,
     font: [u8; 80],
     stack: Vec<u16>,
     delay_timer: u8,
     sound_timer: u8,
-    //64 columns, 32 rows
-    display: [bool; 64*32],
+    //64x32 in low-res mode, 128x64 in SCHIP high-res mode.
+    //Each pixel holds one bit per XO-CHIP bitplane
+    display: Vec<u8>,
+    //XO-CHIP bitplanes affected by drawing, clearing and scrolling
+    selected_planes: u8,
+    //XO-CHIP audio pattern buffer (F002) and pitch (FX3A). Until a rom loads
+    //a pattern the frontend plays its own beep
+    audio_pattern: Option<[u8; 16]>,
+    audio_pitch: u8,
+    hires: bool,
+    halted: bool,
+    //Set whenever the framebuffer changes so frontends only redraw when needed
+    display_changed: bool,
+    //SCHIP RPL user flags (FX75/FX85)
+    rpl_flags: [u8; 16],
     keypad: [bool; 16],
     pc: u16,
     index_register: u16,
     registers: [u8; 16],
-    cycle_speed: i32
+    //Random number source for CXNN, kept here so save states can restore it
+    rng: Box<dyn Rng>,
+    cycle_speed: i32,
+    quirks: Quirks
+}
+
+//SCHIP 8x10 digits, loaded right after the small font
+const BIG_FONT: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+const FONT_START: usize = 0x50;
+const ROM_START: usize = 0x200;
+const STACK_SIZE: usize = 16;
+const BIG_FONT_START: usize = 0xA0;
+const STATE_MAGIC: &[u8; 4] = b"R8ST";
+const STATE_VERSION: u8 = 3;
+
+fn invalid_state(message :&str) -> std::io::Error{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid save state: {}", message))
+}
+
+fn read_u8<R: Read>(reader :&mut R) -> std::io::Result<u8>{
+    let mut buffer = [0; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_u16<R: Read>(reader :&mut R) -> std::io::Result<u16>{
+    let mut buffer = [0; 2];
+    reader.read_exact(&mut buffer)?;
+    Ok(u16::from_be_bytes(buffer))
+}
+
+fn read_u32<R: Read>(reader :&mut R) -> std::io::Result<u32>{
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_be_bytes(buffer))
+}
+
+fn read_bytes<R: Read>(reader :&mut R, len :usize) -> std::io::Result<Vec<u8>>{
+    let mut buffer = vec![0; len];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
 }
 
 impl Chip8 {
 
-    pub fn new(cycle_speed :i32) -> Chip8 {
+    pub fn new(cycle_speed :i32, quirks :Quirks) -> Chip8 {
         Chip8 {
-            memory: [0; 4096],
+            memory: vec![0; 0x10000],
             font : [
                 0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
                 0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -48,24 +119,69 @@ impl Chip8 {
             stack: vec![],
             delay_timer: 0,
             sound_timer: 0,
-            display: [false; 64*32],
+            display: vec![0; 64*32],
+            selected_planes: 1,
+            audio_pattern: None,
+            audio_pitch: 64,
+            hires: false,
+            halted: false,
+            display_changed: true,
+            rpl_flags: [0; 16],
             keypad: [false; 16],
             pc: 0x200,
             index_register: 0,
             registers: [0; 16],
-            cycle_speed
+            rng: Box::new(XorShift::new(random::<u64>())),
+            cycle_speed,
+            quirks
         }
     }
 
-    pub fn get_display(& self) -> [bool; 64*32]{
-        self.display
+    //One value per pixel, bit 0 is plane 1 and bit 1 is plane 2
+    pub fn get_display(& self) -> &[u8]{
+        &self.display
+    }
+
+    //True if the framebuffer changed since the last call
+    pub fn take_display_changed(&mut self) -> bool{
+        std::mem::replace(&mut self.display_changed, false)
+    }
+
+    pub fn display_width(& self) -> usize{
+        if self.hires {128} else {64}
+    }
+
+    pub fn display_height(& self) -> usize{
+        if self.hires {64} else {32}
+    }
+
+    pub fn get_audio_pattern(& self) -> Option<&[u8; 16]>{
+        self.audio_pattern.as_ref()
+    }
+
+    //Rate in Hz at which the 128 bits of the audio pattern are played back
+    pub fn get_audio_playback_rate(& self) -> f32{
+        4000.0 * 2f32.powf((self.audio_pitch as f32 - 64.0)/48.0)
+    }
+
+    //Set once the rom executes 00FD (SCHIP exit)
+    pub fn is_halted(& self) -> bool{
+        self.halted
     }
 
     pub fn debug_display(& self){
-        let mut display :String = String::with_capacity(64*32);
-        for row in 0..32{
-            for col in 0..64{
-                if self.display[col + (64*row)]{
+        let stdout = std::io::stdout();
+        let _ = self.write_display(&mut stdout.lock());
+    }
+
+    //Writes the framebuffer as text, one line per row ('*' lit, ' ' unlit)
+    pub fn write_display<W: Write>(& self, writer :&mut W) -> std::io::Result<()>{
+        let width = self.display_width();
+        let height = self.display_height();
+        let mut display :String = String::with_capacity((width+1)*height);
+        for row in 0..height{
+            for col in 0..width{
+                if self.display[col + (width*row)] != 0{
                     display.push('*');
                 }
                 else{
@@ -75,179 +191,303 @@ impl Chip8 {
             display.push('\n');
         }
 
-        println!("{}", display);
+        writer.write_all(display.as_bytes())
+    }
+
+    pub fn get_sound_timer(& self) -> u8{
+        self.sound_timer
+    }
+
+    pub fn get_delay_timer(& self) -> u8{
+        self.delay_timer
+    }
+
+    //Keypad state as a bitmask, bit N set while key N is held
+    pub fn get_keypad(& self) -> u16{
+        self.keypad.iter().enumerate().fold(0, |mask, (key, pressed)| mask | (*pressed as u16) << key)
+    }
+
+    pub fn get_registers(& self) -> &[u8; 16]{
+        &self.registers
+    }
+
+    pub fn get_index_register(& self) -> u16{
+        self.index_register
+    }
+
+    pub fn get_pc(& self) -> u16{
+        self.pc
+    }
+
+    pub fn get_stack(& self) -> &[u16]{
+        &self.stack
+    }
+
+    pub fn get_memory(& self) -> &[u8]{
+        &self.memory
     }
 
     pub fn get_cycle_speed(& self) -> i32{
         self.cycle_speed
     }
 
-    pub fn read_input(&mut self, key_name : &str, input_state: Key){
-        let mut key_num = 0;
-        match key_name{
-            "1" => key_num = 1,
-            "2" => key_num = 2,
-            "3" => key_num = 3,
-            "4" => key_num = 0xC,
-            "Q" => key_num = 4,
-            "W" => key_num = 5,
-            "E" => key_num = 6,
-            "R" => key_num = 0xD,
-            "A" => key_num = 7,
-            "S" => key_num = 8,
-            "D" => key_num = 9,
-            "F" => key_num = 0xE,
-            "Z" => key_num = 0xA,
-            "X" => key_num = 0,
-            "C" => key_num = 0xB,
-            "V" => key_num = 0xF,
-            _ => ()
-        };
-        
-        // println!("Key: {} pressed", key_num);
-    
-        match input_state{
-            Key::Pressed => self.keypad[key_num] = true,
-            Key::Released => self.keypad[key_num] = false,
+    //Presses or releases one of the 16 keypad keys, anything above F is ignored.
+    //Mapping host input to keys is up to the frontend
+    pub fn set_key(&mut self, key :u8, pressed :bool){
+        if let Some(state) = self.keypad.get_mut(key as usize){
+            *state = pressed;
         }
-        // println!("{}", self.keypad[key_num]);
     }
 
-    pub fn load_rom(&mut self, path:&String){
-        let mut file = File::open(path).expect("Invalid file path");
+    pub fn load_rom(&mut self, path:&String) -> Result<(), Chip8Error>{
+        let read_error = |e :std::io::Error| Chip8Error::RomRead { path: path.clone(), message: e.to_string() };
+        let mut file = File::open(path).map_err(read_error)?;
 
         let mut buffer : Vec<u8> = vec![];
-        let _file_size = file.read_to_end(&mut buffer).expect("Error reading file");
+        file.read_to_end(&mut buffer).map_err(read_error)?;
 
-        let start_position = 0x200;
-        self.memory[start_position..(start_position+buffer.len())].copy_from_slice(&buffer);
-        println!("Sucessfully loaded ROM from path {}", path);
+        self.load_rom_bytes(&buffer)
+    }
+
+    //Copies a rom already in memory to the program area, for frontends without a file system
+    pub fn load_rom_bytes(&mut self, rom :&[u8]) -> Result<(), Chip8Error>{
+        let capacity = self.memory.len() - ROM_START;
+        if rom.len() > capacity{
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), capacity });
+        }
+
+        self.memory[ROM_START..(ROM_START+rom.len())].copy_from_slice(rom);
+        Ok(())
     }
 
     pub fn load_font(&mut self){
-        let font_start = 0x50;
-        self.memory[font_start..(font_start+self.font.len())].copy_from_slice(&self.font);
+        self.memory[FONT_START..(FONT_START+self.font.len())].copy_from_slice(&self.font);
+        self.memory[BIG_FONT_START..(BIG_FONT_START+BIG_FONT.len())].copy_from_slice(&BIG_FONT);
+    }
+
+    fn set_resolution(&mut self, hires :bool){
+        self.hires = hires;
+        self.display = vec![0; self.display_width()*self.display_height()];
+        self.display_changed = true;
+    }
+
+    //Shifts the selected planes by (dx, dy) pixels, filling vacated pixels with black
+    fn scroll(&mut self, dx :i32, dy :i32){
+        let width = self.display_width() as i32;
+        let height = self.display_height() as i32;
+        let planes = self.selected_planes;
+        self.display_changed = true;
+        let mut scrolled :Vec<u8> = self.display.iter().map(|pixel| pixel & !planes).collect();
+        for row in 0..height{
+            for col in 0..width{
+                let src_col = col - dx;
+                let src_row = row - dy;
+                if src_col >= 0 && src_col < width && src_row >= 0 && src_row < height{
+                    scrolled[(col + width*row) as usize] |= self.display[(src_col + width*src_row) as usize] & planes;
+                }
+            }
+        }
+        self.display = scrolled;
+    }
+
+    //Skips the next instruction, which is 4 bytes long if it is an XO-CHIP F000 NNNN
+    fn skip_next(&mut self){
+        let next = ((self.memory[self.pc as usize]) as u16) << 8 | self.memory[self.pc.wrapping_add(1) as usize] as u16;
+        let length = Instruction::decode(next).map_or(2, |instruction| instruction.length());
+        self.pc = self.pc.wrapping_add(length);
+    }
+
+    //XORs a sprite onto one bitplane, one u16 per row with the leftmost pixel in the high bit.
+    //Pixels past the right or bottom edge are clipped, or wrapped with the draw_wraps quirk. Returns true on collision
+    fn draw_sprite(&mut self, x :usize, y :usize, rows :&[u16], sprite_width :usize, plane :u8) -> bool{
+        let width = self.display_width();
+        let height = self.display_height();
+        let x_coord = x % width;
+        let y_coord = y % height;
+        let mut collision = false;
+
+        for (i, row_data) in rows.iter().enumerate(){
+            let mut row = y_coord + i;
+            if row >= height{
+                if !self.quirks.draw_wraps{
+                    break;
+                }
+                row %= height;
+            }
+            for bit in 0..sprite_width{
+                let mut col = x_coord + bit;
+                if col >= width{
+                    if !self.quirks.draw_wraps{
+                        break;
+                    }
+                    col %= width;
+                }
+                if (row_data >> (15-bit)) & 1 == 1{
+                    let pixel = &mut self.display[col + (width*row)];
+                    if *pixel & plane != 0{
+                        collision = true;
+                    }
+                    *pixel ^= plane;
+                }
+            }
+        }
+        collision
     }
 
     fn fetch(&mut self) -> u16 {
-        let instruction = ((self.memory[self.pc as usize]) as u16) << 8 | self.memory[(self.pc+1) as usize] as u16;
-        self.pc+=2;
+        let instruction = ((self.memory[self.pc as usize]) as u16) << 8 | self.memory[self.pc.wrapping_add(1) as usize] as u16;
+        self.pc = self.pc.wrapping_add(2);
         instruction
     }
 
-    pub fn cycle(&mut self){
+    pub fn cycle(&mut self) -> Result<(), Chip8Error>{
+        if self.halted{
+            return Ok(());
+        }
+
         //FETCH
         let instruction :u16 = self.fetch();
+        let pc = self.pc.wrapping_sub(2);
         
 
-        //DECODE+EXECUTE
-        let digit_1 :u16 = (&instruction & 0xF000) >> 12;
-        let digit_2 :u16 = (&instruction & 0x0F00) >> 8;
-        let digit_3 :u16 = (&instruction & 0x00F0) >> 4;
-        let digit_4 :u16 = &instruction & 0x000F;
+        //DECODE
+        let decoded = Instruction::decode(instruction).ok_or(Chip8Error::InvalidOpcode { pc, opcode: instruction })?;
 
-        // let opcode_split: (u16, u16, u16, u16) = (digit_1, digit_2, digit_3, digit_4);
-
-        match (digit_1, digit_2, digit_3, digit_4){
-            (0,0,0,0) => (),
+        //EXECUTE
+        match decoded{
+            Instruction::Nop => (),
             //Clear screan
-            (0,0,0xE,0) => self.display = [false; 64*32],
+            Instruction::ClearScreen => {
+                let planes = self.selected_planes;
+                self.display.iter_mut().for_each(|pixel| *pixel &= !planes);
+                self.display_changed = true;
+            },
+            //SCHIP scroll down N pixels
+            Instruction::ScrollDown { n } => self.scroll(0, n as i32),
+            //XO-CHIP scroll up N pixels
+            Instruction::ScrollUp { n } => self.scroll(0, -(n as i32)),
+            //SCHIP scroll right/left 4 pixels
+            Instruction::ScrollRight => self.scroll(4, 0),
+            Instruction::ScrollLeft => self.scroll(-4, 0),
+            //SCHIP exit interpreter
+            Instruction::Exit => {
+                self.halted = true;
+                self.pc = pc;
+            },
+            //SCHIP low/high resolution
+            Instruction::LowRes => self.set_resolution(false),
+            Instruction::HighRes => self.set_resolution(true),
             //Jump
-            (1, _, _, _) => self.pc = instruction & 0x0FFF,
+            Instruction::Jump { nnn } => self.pc = nnn,
             //Set register VX
-            (6, _, _, _) =>{
-                let nn = (instruction & 0x00FF) as u8;
-                self.registers[digit_2 as usize] = nn;
+            Instruction::LoadImmediate { x, nn } =>{
+                self.registers[x] = nn;
             },
             //Add to register (overflow)?
-            (7, _, _, _) =>{
-                let nn = (instruction & 0x00FF) as u8;
-                self.registers[digit_2 as usize] = self.registers[digit_2 as usize].wrapping_add(nn);
+            Instruction::AddImmediate { x, nn } =>{
+                self.registers[x] = self.registers[x].wrapping_add(nn);
             },
-            (0xA, _, _, _) =>{
-                let nnn = instruction & 0x0FFF;
+            Instruction::LoadIndex { nnn } =>{
                 self.index_register = nnn;
             },
-            //DXYN
-            (0xD, _, _, _) =>{
-                let x_coord :i32 = (self.registers[digit_2  as usize] % 64).into();
-                let mut y_coord :i32 = (self.registers[digit_3 as usize] % 32).into();
-                let sprite_height = digit_4;
-                self.registers[0xF] = 0;
-            
-                'draw_row :for i in 0..sprite_height{
-                    let sprite_data_address = self.index_register + i;
-                    let sprite_pixel_data :u8 = self.memory[sprite_data_address as usize];
-
-                    for bit in (0..8).rev(){
-                        let pixel_bit = (sprite_pixel_data >> bit) & 1;
-                        let curr_pixel = 7-bit;
-                        if (self.display[((x_coord + curr_pixel) + (64 *  y_coord)) as usize] as u8 & pixel_bit) == 1 {
-                            self.display[((x_coord + curr_pixel)+ (64 * y_coord)) as usize] = false;
-                            self.registers[0xF] = 1;
-                        }
-                        else if !self.display[((x_coord + curr_pixel) + (64*y_coord)) as usize] && (pixel_bit == 1){
-                            self.display[((x_coord + curr_pixel) + (64*y_coord)) as usize] = true;
-                        }
-                        
-
-                        if (x_coord) == 64{
-                            break 'draw_row;
-                        } 
+            //DXYN, DXY0 draws a 16x16 SCHIP sprite.
+            //With both XO-CHIP planes selected the plane 2 sprite follows the plane 1 sprite in memory
+            Instruction::Draw { x, y, n } =>{
+                let x_coord = self.registers[x] as usize;
+                let y_coord = self.registers[y] as usize;
+                let mut address = self.index_register as usize;
+                let mut collision = false;
+                self.display_changed = true;
+
+                for plane in [1, 2]{
+                    if self.selected_planes & plane == 0{
+                        continue;
                     }
 
-                    y_coord +=1;
-                    if y_coord == 32{
-                        break 'draw_row;
+                    let (rows, sprite_width) :(Vec<u16>, usize) = if n == 0{
+                        let sprite = &self.memory[self.memory_range(address, 32, pc, instruction)?];
+                        (sprite.chunks(2).map(|row| (row[0] as u16) << 8 | row[1] as u16).collect(), 16)
                     }
-                }
+                    else{
+                        let sprite = &self.memory[self.memory_range(address, n as usize, pc, instruction)?];
+                        (sprite.iter().map(|row| (*row as u16) << 8).collect(), 8)
+                    };
+                    address += rows.len() * (sprite_width/8);
 
+                    collision |= self.draw_sprite(x_coord, y_coord, &rows, sprite_width, plane);
+                }
+                self.registers[0xF] = collision as u8;
             }
             //Return from subroutine
-            (0, 0, 0xE, 0xE) => self.pc = self.stack.pop().expect("Error popping stack value"),
+            Instruction::Return => self.pc = self.stack.pop().ok_or(Chip8Error::StackUnderflow { pc, opcode: instruction })?,
             //Call subroutine
-            (2, _, _, _) =>{
+            Instruction::Call { nnn } =>{
+                if self.stack.len() >= STACK_SIZE{
+                    return Err(Chip8Error::StackOverflow { pc, opcode: instruction });
+                }
                 self.stack.push(self.pc);
-                self.pc = instruction & 0xFFF;
+                self.pc = nnn;
             },
-            (3, _, _, _) =>{
-                let nn = (instruction & 0x00FF) as u8;
-                if self.registers[digit_2 as usize] == nn{
-                    self.pc+=2;
+            Instruction::SkipEqualImmediate { x, nn } =>{
+                if self.registers[x] == nn{
+                    self.skip_next();
                 }
             },
-            (4, _, _, _) =>{
-                let nn = (instruction & 0x00FF) as u8;
-                if self.registers[digit_2 as usize] != nn{
-                    self.pc+=2;
+            Instruction::SkipNotEqualImmediate { x, nn } =>{
+                if self.registers[x] != nn{
+                    self.skip_next();
                 }
             },
-            (5, _, _, 0) =>{
-                if self.registers[digit_2 as usize] == self.registers[digit_3 as usize]{
-                    self.pc+=2;
+            Instruction::SkipEqual { x, y } =>{
+                if self.registers[x] == self.registers[y]{
+                    self.skip_next();
                 }
             },
-            (9, _, _, 0) =>{
-                if self.registers[digit_2 as usize] != self.registers[digit_3 as usize]{
-                    self.pc+=2;
+            //XO-CHIP save/load VX..VY to memory at I, in either direction, leaving I untouched
+            Instruction::SaveRange { x, y } =>{
+                let count = x.abs_diff(y) + 1;
+                let range = self.memory_range(self.index_register as usize, count, pc, instruction)?;
+                for (address, register) in range.zip(Self::register_range(x, y)){
+                    self.memory[address] = self.registers[register];
                 }
             },
-            (8, _, _, 0) =>{
-                self.registers[digit_2 as usize] = self.registers[digit_3 as usize];
+            Instruction::LoadRange { x, y } =>{
+                let count = x.abs_diff(y) + 1;
+                let range = self.memory_range(self.index_register as usize, count, pc, instruction)?;
+                for (address, register) in range.zip(Self::register_range(x, y)){
+                    self.registers[register] = self.memory[address];
+                }
             },
-            (8, _, _, 1) =>{
-                self.registers[digit_2 as usize] |= self.registers[digit_3 as usize];
+            Instruction::SkipNotEqual { x, y } =>{
+                if self.registers[x] != self.registers[y]{
+                    self.skip_next();
+                }
             },
-            (8, _, _, 2) =>{
-                self.registers[digit_2 as usize] &= self.registers[digit_3  as usize];
+            Instruction::Move { x, y } =>{
+                self.registers[x] = self.registers[y];
             },
-            (8, _, _, 3) =>{
-                self.registers[digit_2 as usize] ^= self.registers[digit_3 as usize];
+            Instruction::Or { x, y } =>{
+                self.registers[x] |= self.registers[y];
+                if self.quirks.logic_resets_vf{
+                    self.registers[0xF] = 0;
+                }
             },
-            (8, _, _, 4) =>{
-                let (result, overflow) = self.registers[digit_2 as usize].overflowing_add(self.registers[digit_3 as usize]);
+            Instruction::And { x, y } =>{
+                self.registers[x] &= self.registers[y];
+                if self.quirks.logic_resets_vf{
+                    self.registers[0xF] = 0;
+                }
+            },
+            Instruction::Xor { x, y } =>{
+                self.registers[x] ^= self.registers[y];
+                if self.quirks.logic_resets_vf{
+                    self.registers[0xF] = 0;
+                }
+            },
+            Instruction::Add { x, y } =>{
+                let (result, overflow) = self.registers[x].overflowing_add(self.registers[y]);
 
-                self.registers[digit_2 as usize] = result;
+                self.registers[x] = result;
 
                 if overflow{
                     self.registers[0xF] = 1;
@@ -256,10 +496,10 @@ impl Chip8 {
                     self.registers[0xF] = 0;
                 }
             },
-            (8, _, _, 5) =>{
-                let (result, overflow) = self.registers[digit_2 as usize].overflowing_sub(self.registers[digit_3 as usize]);
+            Instruction::Sub { x, y } =>{
+                let (result, overflow) = self.registers[x].overflowing_sub(self.registers[y]);
             
-                self.registers[digit_2 as usize] = result;
+                self.registers[x] = result;
                 
                 if overflow{
                     self.registers[0xF] = 0;
@@ -268,10 +508,10 @@ impl Chip8 {
                     self.registers[0xF] = 1;
                 }
             },
-            (8, _, _, 7) =>{
-                let (result, overflow) = self.registers[digit_3 as usize].overflowing_sub(self.registers[digit_2 as usize]);
+            Instruction::SubReverse { x, y } =>{
+                let (result, overflow) = self.registers[y].overflowing_sub(self.registers[x]);
                 
-                self.registers[digit_2 as usize] = result;
+                self.registers[x] = result;
 
                 if overflow{
                     self.registers[0xF] = 0;
@@ -280,86 +520,235 @@ impl Chip8 {
                     self.registers[0xF] = 1;
                 }
             },
-            (8, _, _, 6) =>{
-                //Optional
-                // self.registers[digit_2 as usize] = self.registers[digit_3 as usize];
-                let bit = self.registers[digit_2 as usize] & 1;
-                self.registers[digit_2 as usize] >>= 1;
+            Instruction::ShiftRight { x, y } =>{
+                if self.quirks.shift_uses_vy{
+                    self.registers[x] = self.registers[y];
+                }
+                let bit = self.registers[x] & 1;
+                self.registers[x] >>= 1;
                 self.registers[0xF] = bit;
             },
-            (8, _, _, 0xE) =>{
-                //Optional
-                // self.registers[digit_2 as usize] = self.registers[digit_3 as usize];
-                let bit = self.registers[digit_2 as usize].reverse_bits() & 1;
-                self.registers[digit_2 as usize] <<= 1;
+            Instruction::ShiftLeft { x, y } =>{
+                if self.quirks.shift_uses_vy{
+                    self.registers[x] = self.registers[y];
+                }
+                let bit = self.registers[x].reverse_bits() & 1;
+                self.registers[x] <<= 1;
                 self.registers[0xF] = bit;
             },
-            (0xB, _, _, _) =>{
-                let nnn = instruction & 0x0FFF;
-                self.pc = nnn + self.registers[0] as u16;
+            Instruction::JumpOffset { x, nnn } =>{
+                let offset_register = if self.quirks.jump_uses_vx {x} else {0};
+                self.pc = nnn + self.registers[offset_register] as u16;
             },
-            (0xC, _, _, _) =>{
-                let nn :u8 = (instruction & 0x00FF) as u8;
-                let random_num :u8 = random();
-                self.registers[digit_2 as usize] = random_num & nn; 
+            Instruction::Random { x, nn } =>{
+                let random_num :u8 = self.rng.next_byte();
+                self.registers[x] = random_num & nn; 
             },
-            (0xE, _, 9, 0xE) => {
-                let key = self.registers[digit_2 as usize];
+            Instruction::SkipKeyPressed { x } => {
+                let key = self.registers[x] & 0xF;
                 if self.keypad[key as usize]{
-                    self.pc+=2;
+                    self.skip_next();
                 }
             },
-            (0xE, _, 0xA, 1) => {
-                let key = self.registers[digit_2 as usize];
+            Instruction::SkipKeyNotPressed { x } => {
+                let key = self.registers[x] & 0xF;
                 if !self.keypad[key as usize]{
-                    self.pc+=2;
+                    self.skip_next();
                 }
             },  
-            (0xF, _, 0, 7) => {self.registers[digit_2 as usize] = self.delay_timer;},
-            (0xF, _, 1, 5) => {self.delay_timer = self.registers[digit_2 as usize];},
-            (0xF, _, 1, 8) => {self.sound_timer = self.registers[digit_2 as usize];},
-            (0xF, _, 1, 0xE) => {self.index_register += (self.registers[digit_2 as usize]) as u16;},
-            (0xF, _, 0, 0xA) => {
+            //XO-CHIP load I with the 16 bit address that follows
+            Instruction::LoadIndexLong => {
+                self.index_register = self.fetch();
+            },
+            //XO-CHIP select drawing planes
+            Instruction::SelectPlanes { n } => self.selected_planes = n & 0x3,
+            //XO-CHIP load the audio pattern buffer from I
+            Instruction::LoadAudio => {
+                let range = self.memory_range(self.index_register as usize, 16, pc, instruction)?;
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(&self.memory[range]);
+                self.audio_pattern = Some(pattern);
+            },
+            Instruction::SetPitch { x } => self.audio_pitch = self.registers[x],
+            Instruction::GetDelay { x } => {self.registers[x] = self.delay_timer;},
+            Instruction::SetDelay { x } => {self.delay_timer = self.registers[x];},
+            Instruction::SetSound { x } => {self.sound_timer = self.registers[x];},
+            Instruction::AddIndex { x } => {self.index_register = self.index_register.wrapping_add(self.registers[x] as u16);},
+            Instruction::WaitKey { x } => {
                 let mut key_pressed :bool = false;
                 for i in 0..self.keypad.len(){
                     if self.keypad[i]{
-                        self.registers[digit_2 as usize] = i as u8;
+                        self.registers[x] = i as u8;
                         key_pressed = true;
                         break;
                     }
                 }
 
                 if !key_pressed{
-                    self.pc-=2;
+                    self.pc = pc;
                 }
             },
-            (0xF, _, 2, 9) => {
-                let char = self.registers[digit_2 as usize];
-                let char_index = 0x50 + (5*char);
+            Instruction::FontChar { x } => {
+                let char = (self.registers[x] & 0xF) as usize;
+                let char_index = FONT_START + (5*char);
                 self.index_register =  char_index as u16;
             },
-            (0xF, _, 3, 3) =>{
-                let mut num = self.registers[digit_2 as usize];
-                for i in 0..3{
-                    self.memory[(self.index_register + (2-i)) as usize] = num%10;
-                    num/=10;
-                }
+            //SCHIP point I at the 8x10 big font character in VX
+            Instruction::BigFontChar { x } => {
+                let char = (self.registers[x] & 0xF) as usize;
+                self.index_register = (BIG_FONT_START + (10*char)) as u16;
             },
-            (0xF, _, 5, 5) =>{
-                let x = digit_2 as usize;
-                for i in 0..=x{
-                    self.memory[(self.index_register + i as u16) as usize] = self.registers[i];
-                }
+            Instruction::Bcd { x } =>{
+                let num = self.registers[x];
+                let range = self.memory_range(self.index_register as usize, 3, pc, instruction)?;
+                self.memory[range].copy_from_slice(&[num/100, (num/10)%10, num%10]);
             },
-            (0xF, _, 6, 5) =>{
-                let x = digit_2 as usize;
-                for i in 0..=x{
-                    self.registers[i] = self.memory[(self.index_register + i as u16) as usize];
-                }
+            Instruction::Store { x } =>{
+                let range = self.memory_range(self.index_register as usize, x+1, pc, instruction)?;
+                self.memory[range].copy_from_slice(&self.registers[..=x]);
+                self.advance_index(x);
+            },
+            Instruction::Load { x } =>{
+                let range = self.memory_range(self.index_register as usize, x+1, pc, instruction)?;
+                self.registers[..=x].copy_from_slice(&self.memory[range]);
+                self.advance_index(x);
+            }
+            //SCHIP save/restore V0..VX to the RPL user flags
+            Instruction::SaveFlags { x } =>{
+                self.rpl_flags[..=x].copy_from_slice(&self.registers[..=x]);
+            },
+            Instruction::LoadFlags { x } =>{
+                self.registers[..=x].copy_from_slice(&self.rpl_flags[..=x]);
             }
+        }
+        Ok(())
+    }
+
+    //Bounds checks an access of len bytes starting at address and returns the range to index memory with
+    fn memory_range(&self, address :usize, len :usize, pc :u16, opcode :u16) -> Result<Range<usize>, Chip8Error>{
+        if address + len > self.memory.len(){
+            return Err(Chip8Error::MemoryOutOfBounds { pc, opcode, address: address.max(self.memory.len()) });
+        }
+        Ok(address..(address+len))
+    }
+
+    //Moves I on after FX55/FX65 stored or loaded V0..VX, if the quirks say it moves
+    fn advance_index(&mut self, x :usize){
+        if self.quirks.load_store_increments_i{
+            let step = if self.quirks.load_store_increments_i_by_x {x} else {x + 1};
+            self.index_register = self.index_register.wrapping_add(step as u16);
+        }
+    }
+
+    //Registers X..=Y, counting down when X > Y
+    fn register_range(x :usize, y :usize) -> Box<dyn Iterator<Item = usize>>{
+        if x <= y{
+            Box::new(x..=y)
+        }
+        else{
+            Box::new((y..=x).rev())
+        }
+    }
+
+    //Replaces the CXNN random number generator, e.g. with a seeded one for reproducible runs
+    pub fn set_rng(&mut self, rng :Box<dyn Rng>){
+        self.rng = rng;
+    }
+
+    //Writes a snapshot of the whole machine. Quirks and cycle speed are settings, not state,
+    //and aren't included
+    pub fn save_state<W: Write>(& self, writer :&mut W) -> std::io::Result<()>{
+        //Checked first so nothing is written for a generator that doesn't fit
+        let rng_state = self.rng.save();
+        let rng_length = u8::try_from(rng_state.len()).map_err(|_| invalid_state("random number generator state is over 255 bytes"))?;
+        writer.write_all(STATE_MAGIC)?;
+        writer.write_all(&[STATE_VERSION])?;
+        writer.write_all(&(self.memory.len() as u32).to_be_bytes())?;
+        writer.write_all(&self.memory)?;
+        writer.write_all(&self.registers)?;
+        writer.write_all(&self.index_register.to_be_bytes())?;
+        writer.write_all(&self.pc.to_be_bytes())?;
+        writer.write_all(&[self.stack.len() as u8])?;
+        for address in &self.stack{
+            writer.write_all(&address.to_be_bytes())?;
+        }
+        writer.write_all(&[self.delay_timer, self.sound_timer])?;
+        writer.write_all(&[self.hires as u8, self.halted as u8, self.selected_planes])?;
+        writer.write_all(&self.display)?;
+        let keypad :Vec<u8> = self.keypad.iter().map(|key| *key as u8).collect();
+        writer.write_all(&keypad)?;
+        writer.write_all(&self.rpl_flags)?;
+        writer.write_all(&[self.audio_pattern.is_some() as u8])?;
+        writer.write_all(&self.audio_pattern.unwrap_or([0; 16]))?;
+        writer.write_all(&[self.audio_pitch])?;
+        writer.write_all(&[rng_length])?;
+        writer.write_all(&rng_state)?;
+        Ok(())
+    }
+
+    //Restores a snapshot written by save_state. Nothing is changed if the snapshot is invalid
+    pub fn load_state<R: Read>(&mut self, reader :&mut R) -> std::io::Result<()>{
+        if read_bytes(reader, 4)? != STATE_MAGIC{
+            return Err(invalid_state("not a RUST-8 save state"));
+        }
+        let version = read_u8(reader)?;
+        if version != STATE_VERSION{
+            return Err(invalid_state(&format!("unsupported version {}", version)));
+        }
 
-            (_, _, _, _) => {   unimplemented!("Unimplemented opcode: {}", instruction)}
+        let memory_len = read_u32(reader)? as usize;
+        if memory_len != self.memory.len(){
+            return Err(invalid_state("memory size mismatch"));
         }
+        let memory = read_bytes(reader, memory_len)?;
+        let mut registers = [0; 16];
+        reader.read_exact(&mut registers)?;
+        let index_register = read_u16(reader)?;
+        let pc = read_u16(reader)?;
+        let stack_len = read_u8(reader)? as usize;
+        if stack_len > STACK_SIZE{
+            return Err(invalid_state("stack too deep"));
+        }
+        let stack = (0..stack_len).map(|_| read_u16(reader)).collect::<std::io::Result<Vec<u16>>>()?;
+        let delay_timer = read_u8(reader)?;
+        let sound_timer = read_u8(reader)?;
+        let hires = read_u8(reader)? != 0;
+        let halted = read_u8(reader)? != 0;
+        let selected_planes = read_u8(reader)? & 0x3;
+        let display = read_bytes(reader, if hires {128*64} else {64*32})?;
+        let keypad = read_bytes(reader, 16)?;
+        let mut rpl_flags = [0; 16];
+        reader.read_exact(&mut rpl_flags)?;
+        let has_audio_pattern = read_u8(reader)? != 0;
+        let mut audio_pattern = [0; 16];
+        reader.read_exact(&mut audio_pattern)?;
+        let audio_pattern = if has_audio_pattern {Some(audio_pattern)} else {None};
+        let audio_pitch = read_u8(reader)?;
+        let rng_length = read_u8(reader)? as usize;
+        let rng_state = read_bytes(reader, rng_length)?;
+        if !self.rng.restore(&rng_state){
+            return Err(invalid_state("random number generator state doesn't match the generator in use"));
+        }
+
+        self.memory = memory;
+        self.registers = registers;
+        self.index_register = index_register;
+        self.pc = pc;
+        self.stack = stack;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.hires = hires;
+        self.halted = halted;
+        self.selected_planes = selected_planes;
+        self.display = display;
+        self.display_changed = true;
+        for (key, state) in self.keypad.iter_mut().zip(keypad){
+            *key = state != 0;
+        }
+        self.rpl_flags = rpl_flags;
+        self.audio_pattern = audio_pattern;
+        self.audio_pitch = audio_pitch;
+        Ok(())
     }
 
     pub fn decrement_timers(&mut self){
@@ -371,4 +760,366 @@ impl Chip8 {
             self.sound_timer -=1;
         }
     }
-}
\ No newline at end of file
+}
+
+//Machines the tests of every module start from
+#[cfg(test)]
+pub mod test_support{
+    use super::*;
+
+    pub const PONG: &[u8] = include_bytes!("../roms/pong.rom");
+
+    //Pong with the font and a seeded generator, the same run every time for a seed
+    pub fn pong(seed :u64) -> Chip8{
+        let mut chip = program(PONG);
+        chip.load_font();
+        chip.set_rng(Box::new(XorShift::new(seed)));
+        chip
+    }
+
+    //A machine with the default quirks and the rom at 0x200
+    pub fn program(rom :&[u8]) -> Chip8{
+        let mut chip = Chip8::new(700, Quirks::default());
+        chip.load_rom_bytes(rom).unwrap();
+        chip
+    }
+
+    pub fn run(chip :&mut Chip8, cycles :u32){
+        for _i in 0..cycles{
+            chip.cycle().unwrap();
+        }
+    }
+
+    pub fn state(chip :&Chip8) -> Vec<u8>{
+        let mut state = vec![];
+        chip.save_state(&mut state).unwrap();
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use super::test_support::*;
+
+    //Generator whose snapshot doesn't fit the save state's length byte
+    struct BigRng;
+
+    impl Rng for BigRng{
+        fn next_byte(&mut self) -> u8{
+            4
+        }
+
+        fn save(& self) -> Vec<u8>{
+            vec![0; 256]
+        }
+
+        fn restore(&mut self, _state :&[u8]) -> bool{
+            true
+        }
+    }
+
+    #[test]
+    fn loaded_state_continues_the_same_run(){
+        let mut original = pong(1);
+        run(&mut original, 2000);
+        let saved = state(&original);
+
+        //Different seed, so the rng has to come from the state too
+        let mut restored = pong(2);
+        restored.load_state(&mut &saved[..]).unwrap();
+        assert_eq!(state(&restored), saved);
+        run(&mut original, 5000);
+        run(&mut restored, 5000);
+        assert_eq!(state(&restored), state(&original));
+        assert_eq!(restored.get_display(), original.get_display());
+    }
+
+    #[test]
+    fn rejects_bad_states_without_changing_anything(){
+        let mut chip = pong(1);
+        run(&mut chip, 2000);
+        let saved = state(&chip);
+        let mut other = pong(3);
+        let before = state(&other);
+
+        let mut bad_magic = saved.clone();
+        bad_magic[0] = b'X';
+        let mut bad_version = saved.clone();
+        bad_version[4] = STATE_VERSION + 1;
+        let truncated = &saved[..saved.len() - 1];
+        for bad in [&bad_magic[..], &bad_version[..], truncated]{
+            assert!(other.load_state(&mut &bad[..]).is_err());
+            assert_eq!(state(&other), before);
+        }
+    }
+
+    fn lit(chip :&Chip8, col :usize, row :usize) -> bool{
+        chip.get_display()[col + chip.display_width()*row] != 0
+    }
+
+    #[test]
+    fn schip_scrolls_move_the_display(){
+        //LD I, 0x20E; DRW V0, V1, 1; SCD 2; SCR; SCL; SCL; JP 0x20C; one pixel sprite
+        let mut chip = program(&[0xA2, 0x0E, 0xD0, 0x11, 0x00, 0xC2, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC, 0x12, 0x0C, 0x80]);
+        run(&mut chip, 2);
+        assert!(lit(&chip, 0, 0));
+        run(&mut chip, 1);
+        assert!(lit(&chip, 0, 2) && !lit(&chip, 0, 0));
+        run(&mut chip, 1);
+        assert!(lit(&chip, 4, 2) && !lit(&chip, 0, 2));
+        run(&mut chip, 1);
+        assert!(lit(&chip, 0, 2) && !lit(&chip, 4, 2));
+        //Pixels scrolled past the edge are gone
+        run(&mut chip, 1);
+        assert!(chip.get_display().iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn schip_resolution_switches_clear_the_display(){
+        //LD I, 0x20C; DRW V0, V1, 1; HIGH; DRW V0, V1, 1; LOW; EXIT; one pixel sprite
+        let mut chip = program(&[0xA2, 0x0C, 0xD0, 0x11, 0x00, 0xFF, 0xD0, 0x11, 0x00, 0xFE, 0x00, 0xFD, 0x80]);
+        run(&mut chip, 2);
+        assert_eq!((chip.display_width(), chip.display_height()), (64, 32));
+        run(&mut chip, 1);
+        assert_eq!((chip.display_width(), chip.display_height(), chip.get_display().len()), (128, 64, 128*64));
+        assert!(chip.get_display().iter().all(|pixel| *pixel == 0));
+        run(&mut chip, 1);
+        assert!(lit(&chip, 0, 0));
+        run(&mut chip, 1);
+        assert_eq!((chip.display_width(), chip.get_display().len()), (64, 64*32));
+        assert!(chip.get_display().iter().all(|pixel| *pixel == 0));
+        //Exit stops on itself
+        run(&mut chip, 2);
+        assert!(chip.is_halted());
+        assert_eq!(chip.get_pc(), 0x20A);
+    }
+
+    #[test]
+    fn schip_draws_16x16_sprites(){
+        //HIGH; LD I, 0x20A; DRW V0, V1, 0; DRW V0, V1, 0; JP 0x208; 16 rows with both edge pixels lit
+        let mut rom = vec![0x00, 0xFF, 0xA2, 0x0A, 0xD0, 0x10, 0xD0, 0x10, 0x12, 0x08];
+        rom.extend([0x80, 0x01].repeat(16));
+        let mut chip = program(&rom);
+        run(&mut chip, 3);
+        for row in 0..16{
+            assert!(lit(&chip, 0, row) && lit(&chip, 15, row) && !lit(&chip, 1, row));
+        }
+        assert!(!lit(&chip, 0, 16) && !lit(&chip, 16, 0));
+        assert_eq!(chip.get_registers()[0xF], 0);
+        run(&mut chip, 1);
+        assert!(chip.get_display().iter().all(|pixel| *pixel == 0));
+        assert_eq!(chip.get_registers()[0xF], 1);
+    }
+
+    #[test]
+    fn schip_big_font_points_at_8x10_digits(){
+        //LD V0, 7; LD HF, V0
+        let mut chip = program(&[0x60, 0x07, 0xF0, 0x30]);
+        chip.load_font();
+        run(&mut chip, 2);
+        let address = chip.get_index_register() as usize;
+        assert_eq!(address, BIG_FONT_START + 70);
+        assert_eq!(chip.get_memory()[address..address+10], BIG_FONT[70..80]);
+    }
+
+    #[test]
+    fn schip_flags_keep_registers(){
+        //LD V0, 1; LD V1, 2; LD V2, 3; LD R, V2; LD V0, 0; LD V1, 0; LD V2, 9; LD V1, R
+        let mut chip = program(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xF2, 0x75, 0x60, 0x00, 0x61, 0x00, 0x62, 0x09, 0xF1, 0x85]);
+        run(&mut chip, 8);
+        //Only V0..V1 are restored
+        assert_eq!(chip.get_registers()[..3], [1, 2, 9]);
+        //The flags are part of the state
+        let mut restored = program(&[]);
+        restored.load_state(&mut &state(&chip)[..]).unwrap();
+        assert_eq!(restored.rpl_flags[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn xo_chip_long_index_is_skipped_whole(){
+        //LD I, LONG 0x1234; SE V0, 0; LD I, LONG 0xABCD; LD V1, 5
+        let mut chip = program(&[0xF0, 0x00, 0x12, 0x34, 0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0x61, 0x05]);
+        run(&mut chip, 1);
+        assert_eq!((chip.get_index_register(), chip.get_pc()), (0x1234, 0x204));
+        //The skip steps over all 4 bytes instead of running 0xABCD as an instruction
+        run(&mut chip, 2);
+        assert_eq!(chip.get_index_register(), 0x1234);
+        assert_eq!((chip.get_registers()[1], chip.get_pc()), (5, 0x20C));
+    }
+
+    #[test]
+    fn xo_chip_draws_and_clears_selected_planes(){
+        //PLANE 3; LD I, 0x20C; DRW V0, V1, 1; PLANE 2; CLS; JP 0x20A; plane 1 row, plane 2 row
+        let mut chip = program(&[0xF3, 0x01, 0xA2, 0x0C, 0xD0, 0x11, 0xF2, 0x01, 0x00, 0xE0, 0x12, 0x0A, 0x80, 0xC0]);
+        run(&mut chip, 3);
+        assert_eq!(chip.get_display()[..3], [3, 2, 0]);
+        run(&mut chip, 2);
+        assert_eq!(chip.get_display()[..3], [1, 0, 0]);
+    }
+
+    #[test]
+    fn xo_chip_saves_and_loads_register_ranges(){
+        //LD V1, 1; LD V2, 2; LD V3, 3; LD I, 0x300; SAVE V1, V3; LD I, 0x310; SAVE V3, V1; LOAD V5, V7
+        let mut chip = program(&[0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xA3, 0x00, 0x51, 0x32, 0xA3, 0x10, 0x53, 0x12, 0x55, 0x73]);
+        run(&mut chip, 5);
+        assert_eq!(chip.get_memory()[0x300..0x304], [1, 2, 3, 0]);
+        assert_eq!(chip.get_index_register(), 0x300);
+        run(&mut chip, 2);
+        assert_eq!(chip.get_memory()[0x310..0x313], [3, 2, 1]);
+        run(&mut chip, 1);
+        assert_eq!(chip.get_registers()[4..9], [0, 3, 2, 1, 0]);
+        assert_eq!(chip.get_index_register(), 0x310);
+    }
+
+    #[test]
+    fn xo_chip_audio_pattern_and_pitch(){
+        //LD I, 0x20A; AUDIO; LD V0, 112; PITCH V0; JP 0x208; 16 byte pattern
+        let pattern :Vec<u8> = (0..16).collect();
+        let mut rom = vec![0xA2, 0x0A, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A, 0x12, 0x08];
+        rom.extend(&pattern);
+        let mut chip = program(&rom);
+        assert!(chip.get_audio_pattern().is_none());
+        assert_eq!(chip.get_audio_playback_rate(), 4000.0);
+        run(&mut chip, 4);
+        assert_eq!(chip.get_audio_pattern().unwrap()[..], pattern[..]);
+        //48 steps above the default pitch of 64 doubles the rate
+        assert_eq!(chip.get_audio_playback_rate(), 8000.0);
+    }
+
+    //Runs the rom on the default quirks with one flag set both ways, returns (set, cleared)
+    fn both_ways(flag :&str, rom :&[u8], cycles :u32) -> (Chip8, Chip8){
+        let machine = |value :bool| {
+            let mut quirks = Quirks::default();
+            assert!(quirks.set_flag(flag, value));
+            let mut chip = Chip8::new(700, quirks);
+            chip.load_rom_bytes(rom).unwrap();
+            run(&mut chip, cycles);
+            chip
+        };
+        (machine(true), machine(false))
+    }
+
+    #[test]
+    fn shift_uses_vy_quirk(){
+        //LD V0, 0x80; LD V1, 3; SHR V0, V1
+        let (set, cleared) = both_ways("shift_uses_vy", &[0x60, 0x80, 0x61, 0x03, 0x80, 0x16], 3);
+        assert_eq!((set.registers[0], set.registers[0xF]), (1, 1));
+        assert_eq!((cleared.registers[0], cleared.registers[0xF]), (0x40, 0));
+    }
+
+    #[test]
+    fn jump_uses_vx_quirk(){
+        //LD V0, 1; LD V2, 4; JP V0, 0x210
+        let (set, cleared) = both_ways("jump_uses_vx", &[0x60, 0x01, 0x62, 0x04, 0xB2, 0x10], 3);
+        assert_eq!(set.get_pc(), 0x214);
+        assert_eq!(cleared.get_pc(), 0x211);
+    }
+
+    #[test]
+    fn load_store_increments_i_quirk(){
+        //LD I, 0x300; LD [I], V2; LD V2, [I]
+        let rom = [0xA3, 0x00, 0xF2, 0x55, 0xF2, 0x65];
+        let (set, cleared) = both_ways("load_store_increments_i", &rom, 2);
+        assert_eq!(set.get_index_register(), 0x303);
+        assert_eq!(cleared.get_index_register(), 0x300);
+        //Loading moves I the same way
+        let (set, cleared) = both_ways("load_store_increments_i", &rom, 3);
+        assert_eq!(set.get_index_register(), 0x306);
+        assert_eq!(cleared.get_index_register(), 0x300);
+    }
+
+    #[test]
+    fn load_store_increments_i_by_x_quirk(){
+        //LD I, 0x300; LD [I], V2; LD V2, [I]
+        let mut quirks = Quirks::chip48();
+        let mut chip = Chip8::new(700, quirks);
+        chip.load_rom_bytes(&[0xA3, 0x00, 0xF2, 0x55, 0xF2, 0x65]).unwrap();
+        run(&mut chip, 3);
+        assert_eq!(chip.get_index_register(), 0x304);
+        //Without load_store_increments_i it changes nothing
+        quirks.load_store_increments_i = false;
+        let mut chip = Chip8::new(700, quirks);
+        chip.load_rom_bytes(&[0xA3, 0x00, 0xF2, 0x55]).unwrap();
+        run(&mut chip, 2);
+        assert_eq!(chip.get_index_register(), 0x300);
+    }
+
+    #[test]
+    fn draw_wraps_quirk(){
+        //LD V0, 60; LD I, 0x208; DRW V0, V1, 1; 8 pixel wide sprite
+        let (set, cleared) = both_ways("draw_wraps", &[0x60, 0x3C, 0xA2, 0x08, 0xD0, 0x11, 0x00, 0x00, 0xFF], 3);
+        assert!(lit(&set, 63, 0) && lit(&set, 0, 0) && lit(&set, 3, 0) && !lit(&set, 4, 0));
+        assert!(lit(&cleared, 63, 0) && !lit(&cleared, 0, 0));
+    }
+
+    #[test]
+    fn logic_resets_vf_quirk(){
+        //LD VF, 5; OR V0, V1
+        let (set, cleared) = both_ways("logic_resets_vf", &[0x6F, 0x05, 0x80, 0x11], 2);
+        assert_eq!(set.registers[0xF], 0);
+        assert_eq!(cleared.registers[0xF], 5);
+    }
+
+    //Runs the rom until it fails, giving up after a thousand instructions
+    fn error(rom :&[u8]) -> Chip8Error{
+        let mut chip = program(rom);
+        (0..1000).find_map(|_| chip.cycle().err()).expect("the rom didn't fail")
+    }
+
+    #[test]
+    fn invalid_opcodes_are_reported_where_they_are(){
+        //LD V0, 1; 5121 isn't an instruction
+        assert_eq!(error(&[0x60, 0x01, 0x51, 0x21]), Chip8Error::InvalidOpcode { pc: 0x202, opcode: 0x5121 });
+    }
+
+    #[test]
+    fn returning_with_an_empty_stack_underflows(){
+        //CALL 0x204; RET; RET, the call returns to the first RET which has nowhere to go
+        assert_eq!(error(&[0x22, 0x04, 0x00, 0xEE, 0x00, 0xEE]), Chip8Error::StackUnderflow { pc: 0x202, opcode: 0x00EE });
+    }
+
+    #[test]
+    fn calling_with_a_full_stack_overflows(){
+        //LD V0, 1; CALL 0x202, which calls itself until the 16 entries are used up
+        let mut chip = program(&[0x60, 0x01, 0x22, 0x02]);
+        assert_eq!((0..20).find_map(|_| chip.cycle().err()), Some(Chip8Error::StackOverflow { pc: 0x202, opcode: 0x2202 }));
+        assert_eq!(chip.get_stack().len(), STACK_SIZE);
+    }
+
+    #[test]
+    fn accesses_past_the_end_of_memory_fail(){
+        //LD I, LONG 0xFFFE; LD [I], V2 needs 3 bytes but only 2 are left, the
+        //error names the first address past the end
+        assert_eq!(error(&[0xF0, 0x00, 0xFF, 0xFE, 0xF2, 0x55]), Chip8Error::MemoryOutOfBounds { pc: 0x204, opcode: 0xF255, address: 0x10000 });
+        //LD I, LONG 0xFFF0; DRW V0, V1, 0 reads a 32 byte sprite
+        assert_eq!(error(&[0xF0, 0x00, 0xFF, 0xF0, 0xD0, 0x10]), Chip8Error::MemoryOutOfBounds { pc: 0x204, opcode: 0xD010, address: 0x10000 });
+        //The program counter can't leave memory, running 0000 to the end wraps it around to 0
+        let mut chip = program(&[]);
+        run(&mut chip, (0x10000 - 0x200)/2);
+        assert_eq!(chip.get_pc(), 0x0000);
+    }
+
+    #[test]
+    fn roms_that_do_not_fit_or_do_not_exist_fail_to_load(){
+        let mut chip = Chip8::new(700, Quirks::default());
+        assert_eq!(chip.load_rom_bytes(&vec![0; 0x10000 - 0x200 + 1]), Err(Chip8Error::RomTooLarge { size: 0x10000 - 0x200 + 1, capacity: 0x10000 - 0x200 }));
+        assert!(matches!(chip.load_rom(&String::from("roms/missing.rom")), Err(Chip8Error::RomRead { .. })));
+        assert_eq!(Chip8Error::InvalidOpcode { pc: 0x202, opcode: 0x5121 }.to_string(), "Invalid opcode 5121 at 0202");
+    }
+
+    #[test]
+    fn is_send(){
+        fn assert_send<T: Send>(){}
+        assert_send::<Chip8>();
+    }
+
+    #[test]
+    fn rejects_rng_state_that_does_not_fit(){
+        let mut chip = Chip8::new(700, Quirks::default());
+        chip.set_rng(Box::new(BigRng));
+        let mut state = vec![];
+        assert!(chip.save_state(&mut state).is_err());
+        assert!(state.is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+use std::path::Path;
+use std::str::FromStr;
+use rust8::audio::{AudioSettings, Waveform};
+use rust8::headless::RunLength;
+use rust8::keymap::KeyMap;
+use rust8::movie::Movie;
+use rust8::palette::Palette;
+use rust8::quirks::Quirks;
+use rust8::rewind::{DEFAULT_REWIND_SECONDS, MAX_REWIND_SECONDS};
+use crate::config::ConfigSet;
+
+
+//Length of a --headless or test run when neither --frames nor --cycles is given
+pub const DEFAULT_HEADLESS_FRAMES: u32 = 600;
+
+//Instructions per second, far beyond any rom but still a valid i32 cycle speed
+pub const MAX_SPEED: u32 = 10_000_000;
+
+pub const USAGE: &str = "\
+Usage:
+  rust-8 [run] <rom> [options]               run a rom in a window, headless or in the debugger
+  rust-8 test <rom> --expect <dump> [options]
+                                             run a rom headless and compare the final framebuffer
+  rust-8 disasm <rom> [--origin <addr>]      print an assembler listing of a rom
+  rust-8 asm <source> [<output>]             assemble a source file, by default into <source>.ch8
+  rust-8 help                                show this message
+
+Options:
+  --speed <n>            instructions per second (default 700, at most 10000000)
+  --scale <n>            window pixels per CHIP-8 pixel (default 25)
+  --palette <palette>    classic, amber, lcd, grayscale, high-contrast or hex colors like 000000,33ff66
+  --quirks <preset>      vip, chip48, schip or xochip (default schip)
+  --seed <n>             seed the random number generator for reproducible runs
+  --mute                 start with the sound muted
+  --waveform <wave>      square, sine or triangle beep (default square)
+  --frequency <hz>       beep frequency (default 440)
+  --volume <0-1>         beep volume (default 0.25)
+  --rewind <seconds>     history kept for rewinding with Backspace, 0-60 (default 10)
+  --tui                  run in the terminal instead of a window
+  --debug                start paused in the terminal debugger
+  --record <movie>       record the keypad every frame to a movie file
+  --replay <movie>       replay a movie, its seed, speed and quirks replace the options
+  --screenshot <path>    write a PNG of the last frame at --scale and --palette on exit
+  --video <target>       record every frame to a .gif, or '|<command>' pipes raw RGB24
+                         frames to an encoder, {size} in the command becomes WxH
+
+Headless options:
+  --headless             run without a window and print the final framebuffer
+  --frames <n>           run for n frames (implies --headless, default 600)
+  --cycles <n>           run for n instructions (implies --headless)
+  --input <script>       press keys from a script of '<frame> <key> <down|up>' lines
+  --dump <path>          write the final framebuffer to a file, PBM if it ends in .pbm,
+                         a PNG at --scale and --palette if it ends in .png
+  --beeps <path>         write '<first frame> <frames>' for every beep
+  --expect <path>        (test) dump the final framebuffer must match
+
+Roms that aren't found are also looked up in the roms folder. Settings for every rom or
+for single roms can be kept in rust-8.toml or ~/.config/rust-8/config.toml, options
+given on the command line take precedence.";
+
+//Settings for running a rom, shared by run and test
+pub struct RunOptions{
+    pub rom_path: String,
+    pub speed: u32,
+    pub scale: u32,
+    pub palette: Palette,
+    pub quirks: Quirks,
+    pub audio: AudioSettings,
+    pub seed: Option<u64>,
+    pub key_map: KeyMap,
+    pub button_map: KeyMap,
+    pub rewind_seconds: u32,
+    pub debug: bool,
+    //Run in the terminal instead of a window
+    pub tui: bool,
+    //Some when running without a window
+    pub headless: Option<RunLength>,
+    pub input_script: Option<String>,
+    pub dump_path: Option<String>,
+    pub beep_log: Option<String>,
+    //Path the keypad input is recorded to
+    pub record_path: Option<String>,
+    pub replay: Option<Movie>,
+    //PNG of the last frame written when the run ends
+    pub screenshot_path: Option<String>,
+    //GIF path or '|' and an encoder command the run is recorded to
+    pub video_target: Option<String>
+}
+
+impl RunOptions{
+
+    fn new(rom_path :String) -> RunOptions{
+        RunOptions {
+            rom_path,
+            speed: 700,
+            scale: 25,
+            palette: Palette::default(),
+            quirks: Quirks::default(),
+            audio: AudioSettings::default(),
+            seed: None,
+            key_map: KeyMap::default(),
+            button_map: KeyMap::gamepad(),
+            rewind_seconds: DEFAULT_REWIND_SECONDS,
+            debug: false,
+            tui: false,
+            headless: None,
+            input_script: None,
+            dump_path: None,
+            beep_log: None,
+            record_path: None,
+            replay: None,
+            screenshot_path: None,
+            video_target: None
+        }
+    }
+}
+
+pub enum Command{
+    Run(RunOptions),
+    //Runs headless and fails unless the final framebuffer matches the dump at expected_path
+    Test { options: RunOptions, expected_path: String },
+    Disasm { rom_path: String, origin: u16 },
+    Asm { source_path: String, output_path: String },
+    Help
+}
+
+fn parse_value<T: FromStr>(flag :&str, value :&str, expected :&str) -> Result<T, String>{
+    value.parse().map_err(|_| format!("Invalid value '{}' for {}, expected {}", value, flag, expected))
+}
+
+fn parse_address(flag :&str, value :&str) -> Result<u16, String>{
+    let parsed = match value.strip_prefix("0x").or(value.strip_prefix("0X")){
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse()
+    };
+    parsed.map_err(|_| format!("Invalid value '{}' for {}, expected an address like 0x200", value, flag))
+}
+
+//Parses the arguments after the program name. Rom options start from the
+//profile the configs have for the rom, then the flags are applied on top. The
+//configs are only loaded for run and test, so a broken config file doesn't stop
+//help, disasm or asm
+pub fn parse<I: Iterator<Item = String>, L: FnOnce() -> Result<ConfigSet, String>>(args :I, load_configs :L) -> Result<Command, String>{
+    //-h and --help show the usage wherever they are, e.g. after a rom or a subcommand
+    let args :Vec<String> = args.collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help"){
+        return Ok(Command::Help);
+    }
+    let mut args = args.into_iter();
+    let first = args.next().ok_or("Missing rom, try 'rust-8 help'")?;
+    match first.as_str(){
+        "help" => Ok(Command::Help),
+        "disasm" => {
+            let rom_path = args.next().ok_or("Usage: rust-8 disasm <rom> [--origin <addr>]")?;
+            let mut origin = 0x200;
+            while let Some(flag) = args.next(){
+                match flag.as_str(){
+                    "--origin" => origin = parse_address(&flag, &args.next().ok_or("Missing value for --origin")?)?,
+                    _ => return Err(format!("Unknown argument '{}' for disasm", flag))
+                }
+            }
+            Ok(Command::Disasm { rom_path: resolve_rom_path(rom_path), origin })
+        },
+        "asm" => {
+            let source_path = args.next().ok_or("Usage: rust-8 asm <source> [<output>]")?;
+            let output_path = match args.next(){
+                Some(path) => path,
+                None => Path::new(&source_path).with_extension("ch8").to_string_lossy().into_owned()
+            };
+            if let Some(extra) = args.next(){
+                return Err(format!("Unexpected argument '{}' for asm", extra));
+            }
+            Ok(Command::Asm { source_path, output_path })
+        },
+        "test" => {
+            let rom_path = args.next().ok_or("Usage: rust-8 test <rom> --expect <dump> [options]")?;
+            let mut expected_path = None;
+            let mut options = parse_run_options(rom_path, args, &load_configs()?, Some(&mut expected_path))?;
+            let expected_path = expected_path.ok_or("test needs --expect <dump>")?;
+            if options.debug{
+                return Err(String::from("--debug can't be used with test"));
+            }
+            let frames = options.replay.as_ref().map_or(DEFAULT_HEADLESS_FRAMES, |movie| movie.len() as u32);
+            options.headless.get_or_insert(RunLength::Frames(frames));
+            Ok(Command::Test { options, expected_path })
+        },
+        "run" => {
+            let rom_path = args.next().ok_or("Usage: rust-8 run <rom> [options]")?;
+            Ok(Command::Run(parse_run_options(rom_path, args, &load_configs()?, None)?))
+        },
+        flag if flag.starts_with("--") => Err(format!("Expected a rom before {}, try 'rust-8 help'", flag)),
+        _ => Ok(Command::Run(parse_run_options(first, args, &load_configs()?, None)?))
+    }
+}
+
+//expected_path receives --expect, which only test accepts
+fn parse_run_options<I: Iterator<Item = String>>(rom_path :String, mut args :I, configs :&ConfigSet, mut expected_path :Option<&mut Option<String>>) -> Result<RunOptions, String>{
+    let mut options = RunOptions::new(resolve_rom_path(rom_path));
+    configs.profile_for(&options.rom_path).apply(&mut options)?;
+    let mut headless = false;
+
+    while let Some(flag) = args.next(){
+        match flag.as_str(){
+            "--debug" => {options.debug = true; continue;},
+            "--tui" => {options.tui = true; continue;},
+            "--mute" => {options.audio.muted = true; continue;},
+            "--headless" => {headless = true; continue;},
+            _ if !flag.starts_with('-') => return Err(format!("Unexpected argument '{}', try 'rust-8 help'", flag)),
+            _ => ()
+        }
+
+        let value = args.next().ok_or(format!("Missing value for {}", flag))?;
+        match flag.as_str(){
+            "--speed" => options.speed = parse_speed(parse_value(&flag, &value, "a number of instructions per second")?)?,
+            "--scale" => options.scale = parse_value::<u32>(&flag, &value, "a whole number")?.max(1),
+            "--palette" => options.palette = Palette::parse(&value)?,
+            "--quirks" => options.quirks = Quirks::from_name(&value).ok_or(format!("Unknown quirks preset '{}', expected vip, chip48, schip or xochip", value))?,
+            "--rewind" => options.rewind_seconds = parse_rewind_seconds(parse_value(&flag, &value, "a number of seconds")?)?,
+            "--seed" => options.seed = Some(parse_value(&flag, &value, "a whole number")?),
+            "--waveform" => options.audio.waveform = Waveform::from_name(&value).ok_or(format!("Unknown waveform '{}', expected square, sine or triangle", value))?,
+            "--frequency" => options.audio.frequency = parse_value::<f32>(&flag, &value, "a number of Hz")
+                .and_then(|hz| if hz > 0.0 {Ok(hz)} else {Err(String::from("--frequency must be above 0"))})?,
+            "--volume" => options.audio.volume = parse_value::<f32>(&flag, &value, "a number between 0 and 1")
+                .and_then(|volume| if (0.0..=1.0).contains(&volume) {Ok(volume)} else {Err(String::from("--volume must be between 0 and 1"))})?,
+            "--frames" => options.headless = Some(RunLength::Frames(parse_value(&flag, &value, "a number of frames")?)),
+            "--cycles" => options.headless = Some(RunLength::Cycles(parse_value(&flag, &value, "a number of instructions")?)),
+            "--input" => options.input_script = Some(value),
+            "--dump" => options.dump_path = Some(value),
+            "--beeps" => options.beep_log = Some(value),
+            "--record" => options.record_path = Some(value),
+            "--replay" => options.replay = Some(Movie::load(&value)?),
+            "--screenshot" => options.screenshot_path = Some(value),
+            "--video" => options.video_target = Some(value),
+            "--expect" => match expected_path.as_deref_mut(){
+                Some(expected) => *expected = Some(value),
+                None => return Err(String::from("--expect only applies to 'rust-8 test'"))
+            },
+            _ => return Err(format!("Unknown option '{}', try 'rust-8 help'", flag))
+        }
+    }
+
+    //A replay starts the machine exactly as the recording did and by default runs as long
+    if let Some(movie) = &options.replay{
+        if options.record_path.is_some(){
+            return Err(String::from("--record can't be combined with --replay"));
+        }
+        if let Ok(rom) = std::fs::read(&options.rom_path){
+            if rust8::sha1_hex(&rom) != movie.rom_sha1{
+                return Err(format!("The movie was recorded with a different rom (SHA-1 {})", movie.rom_sha1));
+            }
+        }
+        options.seed = Some(movie.seed);
+        options.speed = parse_speed(movie.speed).map_err(|e| format!("{} in the movie", e))?;
+        options.quirks = movie.quirks;
+    }
+    if headless{
+        let frames = options.replay.as_ref().map_or(DEFAULT_HEADLESS_FRAMES, |movie| movie.len() as u32);
+        options.headless.get_or_insert(RunLength::Frames(frames));
+    }
+    if matches!(options.headless, Some(RunLength::Cycles(_))) && (options.record_path.is_some() || options.replay.is_some()){
+        return Err(String::from("Movies are frame by frame, use --frames instead of --cycles"));
+    }
+    if options.debug && options.headless.is_some(){
+        return Err(String::from("--debug can't be combined with a headless run"));
+    }
+    if options.tui && (options.debug || options.headless.is_some()){
+        return Err(String::from("--tui can't be combined with --debug or a headless run"));
+    }
+    if options.debug && (options.record_path.is_some() || options.replay.is_some()){
+        return Err(String::from("--debug can't be combined with a movie"));
+    }
+    if options.debug && (options.screenshot_path.is_some() || options.video_target.is_some()){
+        return Err(String::from("--debug can't be combined with --screenshot or --video"));
+    }
+    Ok(options)
+}
+
+//At least 1, and small enough for the interpreter's signed cycle speed
+pub fn parse_speed(speed :u32) -> Result<u32, String>{
+    if speed > MAX_SPEED{
+        return Err(format!("Speed can be at most {} instructions per second", MAX_SPEED));
+    }
+    Ok(speed.max(1))
+}
+
+pub fn parse_rewind_seconds(seconds :u32) -> Result<u32, String>{
+    if seconds > MAX_REWIND_SECONDS{
+        return Err(format!("Rewind history can be at most {} seconds", MAX_REWIND_SECONDS));
+    }
+    Ok(seconds)
+}
+
+//Roms that don't exist as given are looked up in the roms folder, so
+//'rust-8 pong.rom' keeps working from the repository root
+pub fn resolve_rom_path(path :String) -> String{
+    let in_roms = Path::new("roms").join(&path);
+    if !Path::new(&path).exists() && in_roms.exists(){
+        return in_roms.to_string_lossy().into_owned();
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn parse_with_broken_config(args :&[&str]) -> Result<Command, String>{
+        parse(args.iter().map(|arg| arg.to_string()), || Err(String::from("rust-8.toml: broken")))
+    }
+
+    #[test]
+    fn only_runs_and_tests_load_the_config(){
+        assert!(matches!(parse_with_broken_config(&["--help"]), Ok(Command::Help)));
+        assert!(matches!(parse_with_broken_config(&["pong.rom", "-h"]), Ok(Command::Help)));
+        assert!(matches!(parse_with_broken_config(&["help"]), Ok(Command::Help)));
+        assert!(matches!(parse_with_broken_config(&["disasm", "pong.rom", "--origin", "0x300"]), Ok(Command::Disasm { origin: 0x300, .. })));
+        assert!(matches!(parse_with_broken_config(&["asm", "pong.asm"]), Ok(Command::Asm { .. })));
+
+        for args in [&["pong.rom"][..], &["run", "pong.rom"], &["test", "pong.rom", "--expect", "pong.txt"]]{
+            assert_eq!(parse_with_broken_config(args).err().as_deref(), Some("rust-8.toml: broken"));
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use rust8::keymap::KeyMap;
+use rust8::palette::Palette;
+use rust8::quirks::Quirks;
+use rust8::sha1_hex;
+use crate::cli::{self, RunOptions};
+
+
+//Project level config, looked up in the working directory
+const PROJECT_CONFIG: &str = "rust-8.toml";
+
+//Settings a config file can change. Anything left out keeps its previous value
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile{
+    pub speed: Option<u32>,
+    pub quirks: Option<String>,
+    pub palette: Option<String>,
+    pub scale: Option<u32>,
+    //Seconds of rewind history, 0 turns rewinding off
+    pub rewind: Option<u32>,
+    //Keypad key (0-F) to the keyboard key or keys that press it
+    #[serde(default)]
+    pub keys: BTreeMap<String, HostKeys>,
+    //Keypad key (0-F) to the game controller button or buttons that press it
+    #[serde(default)]
+    pub buttons: BTreeMap<String, HostKeys>
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum HostKeys{
+    One(String),
+    Many(Vec<String>)
+}
+
+impl Profile{
+
+    //Overrides this profile with everything other sets
+    fn merge(&mut self, other :&Profile){
+        if other.speed.is_some(){
+            self.speed = other.speed;
+        }
+        if other.quirks.is_some(){
+            self.quirks.clone_from(&other.quirks);
+        }
+        if other.palette.is_some(){
+            self.palette.clone_from(&other.palette);
+        }
+        if other.scale.is_some(){
+            self.scale = other.scale;
+        }
+        if other.rewind.is_some(){
+            self.rewind = other.rewind;
+        }
+        self.keys.extend(other.keys.iter().map(|(key, name)| (key.clone(), name.clone())));
+        self.buttons.extend(other.buttons.iter().map(|(key, name)| (key.clone(), name.clone())));
+    }
+
+    pub fn apply(& self, options :&mut RunOptions) -> Result<(), String>{
+        if let Some(speed) = self.speed{
+            options.speed = cli::parse_speed(speed).map_err(|e| format!("{} in config", e))?;
+        }
+        if let Some(quirks) = &self.quirks{
+            options.quirks = Quirks::from_name(quirks).ok_or(format!("Unknown quirks preset '{}' in config", quirks))?;
+        }
+        if let Some(palette) = &self.palette{
+            options.palette = Palette::parse(palette).map_err(|e| format!("{} in config", e))?;
+        }
+        if let Some(scale) = self.scale{
+            options.scale = scale.max(1);
+        }
+        if let Some(rewind) = self.rewind{
+            options.rewind_seconds = cli::parse_rewind_seconds(rewind).map_err(|e| format!("{} in config", e))?;
+        }
+        rebind_all(&self.keys, &mut options.key_map)?;
+        rebind_all(&self.buttons, &mut options.button_map)?;
+        Ok(())
+    }
+}
+
+fn rebind_all(bindings :&BTreeMap<String, HostKeys>, key_map :&mut KeyMap) -> Result<(), String>{
+    for (key, host_keys) in bindings{
+        let key = u8::from_str_radix(key, 16).ok().filter(|key| *key < 16)
+            .ok_or(format!("Invalid keypad key '{}' in config, expected 0-F", key))?;
+        match host_keys{
+            HostKeys::One(host_key) => key_map.rebind(key, std::slice::from_ref(host_key)),
+            HostKeys::Many(host_keys) => key_map.rebind(key, host_keys)
+        }
+    }
+    Ok(())
+}
+
+//One config file: defaults for every rom plus profiles for single roms, keyed
+//by the rom's SHA-1 or its file name
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config{
+    #[serde(default)]
+    pub defaults: Profile,
+    #[serde(default)]
+    pub roms: BTreeMap<String, Profile>
+}
+
+impl Config{
+
+    pub fn parse(text :&str) -> Result<Config, String>{
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    //Profiles for a rom in the order they apply: a file name match, then a SHA-1 match
+    fn rom_profiles<'a>(&'a self, file_name :&str, sha1 :&str) -> impl Iterator<Item = &'a Profile>{
+        let by_name = self.roms.get(file_name);
+        let by_hash = self.roms.iter().find(|(key, _)| !sha1.is_empty() && key.eq_ignore_ascii_case(sha1)).map(|(_, rom)| rom);
+        by_name.into_iter().chain(by_hash)
+    }
+}
+
+//Every config that exists, user level first so the project config wins
+pub struct ConfigSet{
+    configs: Vec<Config>
+}
+
+impl ConfigSet{
+
+    pub fn load() -> Result<ConfigSet, String>{
+        let mut configs = vec![];
+        for path in user_config_path().into_iter().chain([PathBuf::from(PROJECT_CONFIG)]){
+            if !path.exists(){
+                continue;
+            }
+            let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            configs.push(Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?);
+        }
+        Ok(ConfigSet { configs })
+    }
+
+    //Merged profile for the rom at rom_path. Roms that can't be read only get the defaults
+    pub fn profile_for(& self, rom_path :&str) -> Profile{
+        let file_name = Path::new(rom_path).file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        let sha1 = fs::read(rom_path).map_or(String::new(), |rom| sha1_hex(&rom));
+        self.merged_profile(&file_name, &sha1)
+    }
+
+    //Every file's defaults first, then every file's rom profiles, so a rom profile
+    //in the user config still beats the project defaults
+    fn merged_profile(& self, file_name :&str, sha1 :&str) -> Profile{
+        let mut profile = Profile::default();
+        for config in &self.configs{
+            profile.merge(&config.defaults);
+        }
+        for config in &self.configs{
+            for rom in config.rom_profiles(file_name, sha1){
+                profile.merge(rom);
+            }
+        }
+        profile
+    }
+}
+
+//$XDG_CONFIG_HOME/rust-8/config.toml, ~/.config/rust-8/config.toml or %APPDATA%\rust-8\config.toml
+fn user_config_path() -> Option<PathBuf>{
+    let base = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(base.join("rust-8").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn configs(texts :&[&str]) -> ConfigSet{
+        ConfigSet { configs: texts.iter().map(|text| Config::parse(text).unwrap()).collect() }
+    }
+
+    #[test]
+    fn rom_profiles_beat_defaults_from_any_file(){
+        let user = "[roms.\"pong.rom\"]\nspeed = 500\n";
+        let project = "[defaults]\nspeed = 900\nscale = 10\n";
+        let profile = configs(&[user, project]).merged_profile("pong.rom", "");
+        assert_eq!(profile.speed, Some(500));
+        assert_eq!(profile.scale, Some(10));
+        assert_eq!(configs(&[user, project]).merged_profile("other.rom", "").speed, Some(900));
+    }
+
+    #[test]
+    fn later_files_and_hash_matches_win(){
+        let user = "[defaults]\npalette = \"amber\"\n[roms.\"pong.rom\"]\nspeed = 500\n";
+        let project = "[defaults]\npalette = \"lcd\"\n[roms.ABCDEF]\nspeed = 600\n[roms.\"pong.rom\"]\nquirks = \"vip\"\n";
+        let profile = configs(&[user, project]).merged_profile("pong.rom", "abcdef");
+        assert_eq!(profile.palette.as_deref(), Some("lcd"));
+        assert_eq!(profile.speed, Some(600));
+        assert_eq!(profile.quirks.as_deref(), Some("vip"));
+    }
+
+    #[test]
+    fn key_bindings_replace_the_default_ones(){
+        let project = "[defaults]\nkeys = { \"5\" = \"Up\", \"8\" = [\"Down\", \"K\"] }\nbuttons = { \"5\" = \"start\" }\n";
+        let options = match cli::parse([String::from("pong.rom")].into_iter(), || Ok(configs(&[project]))){
+            Ok(cli::Command::Run(options)) => options,
+            _ => panic!("expected a run")
+        };
+        assert_eq!((options.key_map.get("up"), options.key_map.get("k")), (Some(0x5), Some(0x8)));
+        //W was 5 and S was 8 in the default layout, the other keys stay
+        assert_eq!((options.key_map.get("W"), options.key_map.get("S"), options.key_map.get("Q")), (None, None, Some(0x4)));
+        assert_eq!((options.button_map.get("start"), options.button_map.get("a")), (Some(0x5), None));
+
+        let bad = "[defaults]\nkeys = { \"G\" = \"Up\" }\n";
+        assert!(cli::parse([String::from("pong.rom")].into_iter(), || Ok(configs(&[bad]))).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::io::{self, BufRead, Write};
+use rust8::Chip8;
+use rust8::debugger::{Debugger, StopReason, Watchpoint};
+use rust8::disasm;
+use rust8::opcode::Instruction;
+
+
+//Instructions run by continue/next/finish before giving control back
+const DEFAULT_LIMIT: u32 = 1_000_000;
+
+const HELP: &str = "\
+Commands:
+  s, step [n]            execute n instructions (default 1)
+  n, next                step over 2NNN calls
+  finish                 run until the current subroutine returns
+  c, continue [n]        run until a breakpoint, watchpoint or n instructions
+  b, break <addr>        set a breakpoint
+  d, delete <addr>       remove a breakpoint
+  watch <vX|i|addr>      stop when a register, I or a memory byte changes
+  unwatch <vX|i|addr>    remove a watchpoint
+  info b|w               list breakpoints or watchpoints
+  r, regs                show registers, I, PC, stack and timers
+  x <addr> [len]         dump memory
+  display                print the framebuffer
+  key <0-F> down|up      press or release a keypad key
+  q, quit                exit the debugger
+An empty line repeats the last command.";
+
+fn parse_number(text :&str) -> Result<u32, String>{
+    let parsed = match text.strip_prefix("0x").or(text.strip_prefix("0X")){
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse()
+    };
+    parsed.map_err(|_| format!("Invalid number '{}'", text))
+}
+
+fn parse_address(text :&str) -> Result<u16, String>{
+    let address = parse_number(text)?;
+    u16::try_from(address).map_err(|_| format!("Address {} is out of range", text))
+}
+
+fn parse_watchpoint(text :&str) -> Result<Watchpoint, String>{
+    let lower = text.to_lowercase();
+    if lower == "i"{
+        return Ok(Watchpoint::Index);
+    }
+    if let Some(register) = lower.strip_prefix('v'){
+        return usize::from_str_radix(register, 16).ok().filter(|x| *x < 16)
+            .map(Watchpoint::Register)
+            .ok_or(format!("Invalid register '{}'", text));
+    }
+    Ok(Watchpoint::Memory(parse_address(text)?))
+}
+
+fn describe_watchpoint(watch :&Watchpoint) -> String{
+    match watch{
+        Watchpoint::Register(x) => format!("V{:X}", x),
+        Watchpoint::Index => String::from("I"),
+        Watchpoint::Memory(address) => format!("[{:04X}]", address)
+    }
+}
+
+fn print_location(emulator :&Chip8){
+    let pc = emulator.get_pc() as usize;
+    let memory = emulator.get_memory();
+    let word = |address :usize| (memory[address % memory.len()] as u16) << 8 | memory[(address+1) % memory.len()] as u16;
+    let opcode = word(pc);
+    let text = match Instruction::decode(opcode){
+        Some(instruction) => disasm::describe(&instruction, word(pc+2)),
+        None => String::from("(invalid)")
+    };
+    println!("{:04X}: {:04X}  {}", pc, opcode, text);
+}
+
+fn print_registers(emulator :&Chip8){
+    let registers = emulator.get_registers();
+    for (x, value) in registers.iter().enumerate(){
+        print!("V{:X}={:02X}{}", x, value, if x % 8 == 7 {"\n"} else {" "});
+    }
+    println!("I={:04X} PC={:04X} DT={:02X} ST={:02X}", emulator.get_index_register(), emulator.get_pc(), emulator.get_delay_timer(), emulator.get_sound_timer());
+    let stack :Vec<String> = emulator.get_stack().iter().map(|address| format!("{:04X}", address)).collect();
+    println!("Stack: [{}]", stack.join(", "));
+}
+
+fn print_memory(emulator :&Chip8, address :u16, len :usize){
+    let memory = emulator.get_memory();
+    let start = address as usize;
+    let end = (start + len).min(memory.len());
+    for (row, bytes) in memory[start..end].chunks(16).enumerate(){
+        let hex :Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        println!("{:04X}: {}", start + 16*row, hex.join(" "));
+    }
+}
+
+fn report(reason :StopReason, emulator :&Chip8){
+    match reason{
+        StopReason::Done => (),
+        StopReason::Breakpoint(address) => println!("Breakpoint at {:04X}", address),
+        StopReason::Watchpoint { watch, old, new } => println!("Watchpoint {}: {:X} -> {:X}", describe_watchpoint(&watch), old, new),
+        StopReason::Halted => println!("Rom exited"),
+        StopReason::Error(error) => println!("{}", error),
+        StopReason::Limit => println!("Stopped after the instruction limit")
+    }
+    print_location(emulator);
+}
+
+//Runs one command, returns false when the user asked to quit
+fn execute(command :&str, debugger :&mut Debugger, emulator :&mut Chip8) -> Result<bool, String>{
+    let parts :Vec<&str> = command.split_whitespace().collect();
+    let argument = |index :usize| parts.get(index).copied().ok_or(String::from("Missing argument, try 'help'"));
+
+    match parts[0]{
+        "s" | "step" => {
+            let count = match parts.get(1){
+                Some(count) => parse_number(count)?,
+                None => 1
+            };
+            let mut reason = StopReason::Done;
+            for _i in 0..count{
+                reason = debugger.step(emulator);
+                if !matches!(reason, StopReason::Done){
+                    break;
+                }
+            }
+            report(reason, emulator);
+        },
+        "n" | "next" => report(debugger.step_over(emulator, DEFAULT_LIMIT), emulator),
+        "finish" => {
+            if emulator.get_stack().is_empty(){
+                return Err(String::from("Not inside a subroutine"));
+            }
+            report(debugger.run_to_return(emulator, DEFAULT_LIMIT), emulator);
+        },
+        "c" | "continue" => {
+            let limit = match parts.get(1){
+                Some(limit) => parse_number(limit)?,
+                None => DEFAULT_LIMIT
+            };
+            report(debugger.resume(emulator, limit), emulator);
+        },
+        "b" | "break" => {
+            let address = parse_address(argument(1)?)?;
+            debugger.add_breakpoint(address);
+            println!("Breakpoint set at {:04X}", address);
+        },
+        "d" | "delete" => {
+            let address = parse_address(argument(1)?)?;
+            if !debugger.remove_breakpoint(address){
+                println!("No breakpoint at {:04X}", address);
+            }
+        },
+        "watch" => {
+            let watch = parse_watchpoint(argument(1)?)?;
+            debugger.add_watchpoint(watch);
+            println!("Watching {}", describe_watchpoint(&watch));
+        },
+        "unwatch" => {
+            let watch = parse_watchpoint(argument(1)?)?;
+            if !debugger.remove_watchpoint(watch){
+                println!("Not watching {}", describe_watchpoint(&watch));
+            }
+        },
+        "info" => match argument(1)?{
+            "b" | "breakpoints" => debugger.get_breakpoints().for_each(|address| println!("{:04X}", address)),
+            "w" | "watchpoints" => debugger.get_watchpoints().iter().for_each(|watch| println!("{}", describe_watchpoint(watch))),
+            "r" | "registers" => print_registers(emulator),
+            other => return Err(format!("Unknown info '{}'", other))
+        },
+        "r" | "regs" => print_registers(emulator),
+        "x" => {
+            let address = parse_address(argument(1)?)?;
+            let len = match parts.get(2){
+                Some(len) => parse_number(len)? as usize,
+                None => 16
+            };
+            print_memory(emulator, address, len);
+        },
+        "display" => emulator.debug_display(),
+        "key" => {
+            let key = u8::from_str_radix(argument(1)?, 16).ok().filter(|k| *k < 16)
+                .ok_or(format!("Invalid key '{}'", parts[1]))?;
+            match argument(2)?{
+                "down" => emulator.set_key(key, true),
+                "up" => emulator.set_key(key, false),
+                other => return Err(format!("Expected 'down' or 'up', found '{}'", other))
+            }
+        },
+        "h" | "help" => println!("{}", HELP),
+        "q" | "quit" => return Ok(false),
+        other => return Err(format!("Unknown command '{}', try 'help'", other))
+    }
+    Ok(true)
+}
+
+//Interactive gdb-like debugger reading commands from stdin
+pub fn run(emulator :&mut Chip8) -> Result<(), String>{
+    let mut debugger = Debugger::new(emulator);
+    let stdin = io::stdin();
+    let mut last_command = String::new();
+
+    print_location(emulator);
+    loop{
+        print!("(rust-8) ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).map_err(|e| e.to_string())? == 0{
+            return Ok(());
+        }
+
+        let command = if line.trim().is_empty() {last_command.clone()} else {line.trim().to_string()};
+        if command.is_empty(){
+            continue;
+        }
+
+        match execute(&command, &mut debugger, emulator){
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            Err(message) => println!("{}", message)
+        }
+        last_command = command;
+    }
+}
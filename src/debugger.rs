@@ -0,0 +1,221 @@
+use std::collections::BTreeSet;
+use crate::Chip8;
+use crate::error::Chip8Error;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Watchpoint{
+    Register(usize),
+    Index,
+    Memory(u16)
+}
+
+//Why execution stopped
+#[derive(Debug)]
+pub enum StopReason{
+    //The requested step/step over/run to return finished
+    Done,
+    Breakpoint(u16),
+    Watchpoint { watch: Watchpoint, old: u16, new: u16 },
+    //The rom executed 00FD
+    Halted,
+    Error(Chip8Error),
+    //Ran for the instruction limit without stopping
+    Limit
+}
+
+pub struct Debugger{
+    breakpoints :BTreeSet<u16>,
+    watchpoints :Vec<Watchpoint>,
+    cycles_per_frame :u32,
+    cycles :u32
+}
+
+impl Debugger{
+
+    pub fn new(emulator :&Chip8) -> Debugger{
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: vec![],
+            cycles_per_frame: (emulator.get_cycle_speed()/60).max(1) as u32,
+            cycles: 0
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address :u16){
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address :u16) -> bool{
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn get_breakpoints(& self) -> impl Iterator<Item = &u16>{
+        self.breakpoints.iter()
+    }
+
+    pub fn add_watchpoint(&mut self, watch :Watchpoint){
+        if !self.watchpoints.contains(&watch){
+            self.watchpoints.push(watch);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watch :Watchpoint) -> bool{
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| *w != watch);
+        self.watchpoints.len() != count
+    }
+
+    pub fn get_watchpoints(& self) -> &[Watchpoint]{
+        &self.watchpoints
+    }
+
+    pub fn read_watchpoint(emulator :&Chip8, watch :Watchpoint) -> u16{
+        match watch{
+            Watchpoint::Register(x) => emulator.get_registers()[x] as u16,
+            Watchpoint::Index => emulator.get_index_register(),
+            Watchpoint::Memory(address) => emulator.get_memory()[address as usize] as u16
+        }
+    }
+
+    //Executes a single instruction
+    pub fn step(&mut self, emulator :&mut Chip8) -> StopReason{
+        self.run_until(emulator, 1, |_| true)
+    }
+
+    //Like step, but runs a 2NNN call until it returns to the next instruction
+    pub fn step_over(&mut self, emulator :&mut Chip8, limit :u32) -> StopReason{
+        let pc = emulator.get_pc() as usize;
+        let memory = emulator.get_memory();
+        let is_call = memory[pc] >> 4 == 2;
+        if !is_call{
+            return self.step(emulator);
+        }
+
+        let return_address = emulator.get_pc().wrapping_add(2);
+        let depth = emulator.get_stack().len();
+        self.run_until(emulator, limit, |chip| chip.get_pc() == return_address && chip.get_stack().len() == depth)
+    }
+
+    //Runs until the current subroutine returns to its caller.
+    //Outside of a subroutine this behaves like resume
+    pub fn run_to_return(&mut self, emulator :&mut Chip8, limit :u32) -> StopReason{
+        let depth = emulator.get_stack().len();
+        self.run_until(emulator, limit, |chip| chip.get_stack().len() < depth)
+    }
+
+    //Runs until a breakpoint or watchpoint is hit
+    pub fn resume(&mut self, emulator :&mut Chip8, limit :u32) -> StopReason{
+        self.run_until(emulator, limit, |_| false)
+    }
+
+    //Executes instructions until done returns true, checking watchpoints after every
+    //instruction and breakpoints before every instruction but the first
+    fn run_until<F: Fn(&Chip8) -> bool>(&mut self, emulator :&mut Chip8, limit :u32, done :F) -> StopReason{
+        for _i in 0..limit{
+            if emulator.is_halted(){
+                return StopReason::Halted;
+            }
+
+            let watched :Vec<u16> = self.watchpoints.iter().map(|w| Self::read_watchpoint(emulator, *w)).collect();
+
+            if let Err(error) = emulator.cycle(){
+                return StopReason::Error(error);
+            }
+            self.cycles+=1;
+            if self.cycles.is_multiple_of(self.cycles_per_frame){
+                emulator.decrement_timers();
+            }
+
+            for (watch, old) in self.watchpoints.iter().zip(watched){
+                let new = Self::read_watchpoint(emulator, *watch);
+                if new != old{
+                    return StopReason::Watchpoint { watch: *watch, old, new };
+                }
+            }
+
+            if done(emulator){
+                return StopReason::Done;
+            }
+
+            if self.breakpoints.contains(&emulator.get_pc()){
+                return StopReason::Breakpoint(emulator.get_pc());
+            }
+        }
+
+        StopReason::Limit
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::chip8::test_support::program;
+
+    //CALL 0x208; LD V0, 7; JP 0x204; -; LD V1, 1; LD V2, 2; RET
+    const CALLER: [u8; 14] = [0x22, 0x08, 0x60, 0x07, 0x12, 0x04, 0x00, 0x00, 0x61, 0x01, 0x62, 0x02, 0x00, 0xEE];
+
+    #[test]
+    fn step_over_runs_the_whole_call(){
+        let mut chip = program(&CALLER);
+        let mut debugger = Debugger::new(&chip);
+        assert!(matches!(debugger.step_over(&mut chip, 100), StopReason::Done));
+        assert_eq!((chip.get_pc(), chip.get_stack().len()), (0x202, 0));
+        assert_eq!(chip.get_registers()[1..3], [1, 2]);
+        //Anything but a call is a single step
+        assert!(matches!(debugger.step_over(&mut chip, 100), StopReason::Done));
+        assert_eq!((chip.get_pc(), chip.get_registers()[0]), (0x204, 7));
+    }
+
+    #[test]
+    fn runs_to_the_return_of_the_current_call(){
+        let mut chip = program(&CALLER);
+        let mut debugger = Debugger::new(&chip);
+        debugger.step(&mut chip);
+        assert_eq!((chip.get_pc(), chip.get_stack().len()), (0x208, 1));
+        assert!(matches!(debugger.run_to_return(&mut chip, 100), StopReason::Done));
+        assert_eq!((chip.get_pc(), chip.get_stack().len()), (0x202, 0));
+        assert_eq!(chip.get_registers()[2], 2);
+    }
+
+    #[test]
+    fn resuming_from_a_breakpoint_runs_past_it(){
+        let mut chip = program(&CALLER);
+        let mut debugger = Debugger::new(&chip);
+        debugger.add_breakpoint(0x20A);
+        assert!(matches!(debugger.resume(&mut chip, 100), StopReason::Breakpoint(0x20A)));
+        assert_eq!((chip.get_pc(), chip.get_registers()[2]), (0x20A, 0));
+        //The instruction the breakpoint stopped before runs instead of stopping again
+        assert!(matches!(debugger.resume(&mut chip, 100), StopReason::Limit));
+        assert_eq!(chip.get_registers()[2], 2);
+    }
+
+    #[test]
+    fn watchpoints_report_old_and_new_values(){
+        let mut chip = program(&CALLER);
+        let mut debugger = Debugger::new(&chip);
+        debugger.add_watchpoint(Watchpoint::Register(2));
+        assert!(matches!(debugger.resume(&mut chip, 100), StopReason::Watchpoint { watch: Watchpoint::Register(2), old: 0, new: 2 }));
+        assert_eq!(chip.get_pc(), 0x20C);
+
+        //LD I, 0x300; LD V0, 5; LD [I], V0
+        let mut chip = program(&[0xA3, 0x00, 0x60, 0x05, 0xF0, 0x55]);
+        let mut debugger = Debugger::new(&chip);
+        debugger.add_watchpoint(Watchpoint::Memory(0x300));
+        debugger.add_watchpoint(Watchpoint::Index);
+        assert!(matches!(debugger.resume(&mut chip, 100), StopReason::Watchpoint { watch: Watchpoint::Index, old: 0, new: 0x300 }));
+        assert!(matches!(debugger.resume(&mut chip, 100), StopReason::Watchpoint { watch: Watchpoint::Memory(0x300), old: 0, new: 5 }));
+        assert_eq!(chip.get_pc(), 0x206);
+    }
+
+    #[test]
+    fn stops_at_the_limit(){
+        //ADD V3, 1; JP 0x200
+        let mut chip = program(&[0x73, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new(&chip);
+        assert!(matches!(debugger.resume(&mut chip, 10), StopReason::Limit));
+        assert_eq!(chip.get_registers()[3], 5);
+        assert!(matches!(debugger.run_to_return(&mut chip, 10), StopReason::Limit));
+        assert_eq!(chip.get_registers()[3], 10);
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeSet;
+use crate::opcode::Instruction;
+
+
+//Formats an instruction as assembler text. long_operand is the word following
+//F000 and address turns jump/call targets into text
+fn format_instruction(instruction :&Instruction, long_operand :u16, address :&dyn Fn(u16) -> String) -> String{
+    match *instruction{
+        Instruction::Nop => String::from("NOP"),
+        Instruction::ClearScreen => String::from("CLS"),
+        Instruction::Return => String::from("RET"),
+        Instruction::ScrollDown { n } => format!("SCD {}", n),
+        Instruction::ScrollUp { n } => format!("SCU {}", n),
+        Instruction::ScrollRight => String::from("SCR"),
+        Instruction::ScrollLeft => String::from("SCL"),
+        Instruction::Exit => String::from("EXIT"),
+        Instruction::LowRes => String::from("LOW"),
+        Instruction::HighRes => String::from("HIGH"),
+        Instruction::Jump { nnn } => format!("JP {}", address(nnn)),
+        Instruction::Call { nnn } => format!("CALL {}", address(nnn)),
+        Instruction::SkipEqualImmediate { x, nn } => format!("SE V{:X}, 0x{:02X}", x, nn),
+        Instruction::SkipNotEqualImmediate { x, nn } => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        Instruction::SkipEqual { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::SaveRange { x, y } => format!("SAVE V{:X}, V{:X}", x, y),
+        Instruction::LoadRange { x, y } => format!("LOAD V{:X}, V{:X}", x, y),
+        Instruction::LoadImmediate { x, nn } => format!("LD V{:X}, 0x{:02X}", x, nn),
+        Instruction::AddImmediate { x, nn } => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        Instruction::Move { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::Add { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::ShiftRight { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::SubReverse { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::ShiftLeft { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SkipNotEqual { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LoadIndex { nnn } => format!("LD I, 0x{:03X}", nnn),
+        //X is the high digit of the address. With the jump_uses_vx quirk (SCHIP, the default)
+        //this jumps to the address plus VX, without it to the address plus V0
+        Instruction::JumpOffset { x, nnn } => format!("JP V{:X}, {}", x, address(nnn)),
+        Instruction::Random { x, nn } => format!("RND V{:X}, 0x{:02X}", x, nn),
+        Instruction::Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::SkipKeyPressed { x } => format!("SKP V{:X}", x),
+        Instruction::SkipKeyNotPressed { x } => format!("SKNP V{:X}", x),
+        Instruction::LoadIndexLong => format!("LD I, LONG 0x{:04X}", long_operand),
+        Instruction::SelectPlanes { n } => format!("PLANE {}", n),
+        Instruction::LoadAudio => String::from("AUDIO"),
+        Instruction::GetDelay { x } => format!("LD V{:X}, DT", x),
+        Instruction::WaitKey { x } => format!("LD V{:X}, K", x),
+        Instruction::SetDelay { x } => format!("LD DT, V{:X}", x),
+        Instruction::SetSound { x } => format!("LD ST, V{:X}", x),
+        Instruction::AddIndex { x } => format!("ADD I, V{:X}", x),
+        Instruction::FontChar { x } => format!("LD F, V{:X}", x),
+        Instruction::BigFontChar { x } => format!("LD HF, V{:X}", x),
+        Instruction::Bcd { x } => format!("LD B, V{:X}", x),
+        Instruction::SetPitch { x } => format!("PITCH V{:X}", x),
+        Instruction::Store { x } => format!("LD [I], V{:X}", x),
+        Instruction::Load { x } => format!("LD V{:X}, [I]", x),
+        Instruction::SaveFlags { x } => format!("LD R, V{:X}", x),
+        Instruction::LoadFlags { x } => format!("LD V{:X}, R", x)
+    }
+}
+
+//Assembler text for a single instruction, with jump/call targets as plain addresses
+pub fn describe(instruction :&Instruction, long_operand :u16) -> String{
+    format_instruction(instruction, long_operand, &|address| format!("0x{:03X}", address))
+}
+
+//Disassembles a rom loaded at origin into a listing with one instruction per line.
+//Words that don't decode are listed as DB data bytes, and every jump/call target
+//that lines up with an instruction gets an L<address> label. Addresses and raw
+//bytes go in comments so the listing can be fed back to the assembler
+pub fn disassemble(rom :&[u8], origin :u16) -> String{
+    //(address, bytes, decoded instruction)
+    let mut lines :Vec<(u16, &[u8], Option<Instruction>)> = vec![];
+    let mut offset = 0;
+    while offset < rom.len(){
+        let address = origin.wrapping_add(offset as u16);
+        if offset+1 >= rom.len(){
+            lines.push((address, &rom[offset..], None));
+            break;
+        }
+
+        let opcode = (rom[offset] as u16) << 8 | rom[offset+1] as u16;
+        let instruction = Instruction::decode(opcode).filter(|i| offset + i.length() as usize <= rom.len());
+        let length = instruction.map_or(2, |i| i.length() as usize);
+        lines.push((address, &rom[offset..offset+length], instruction));
+        offset += length;
+    }
+
+    let addresses :BTreeSet<u16> = lines.iter().map(|line| line.0).collect();
+    let targets :BTreeSet<u16> = lines.iter().filter_map(|line| match line.2{
+        Some(Instruction::Jump { nnn }) | Some(Instruction::Call { nnn }) | Some(Instruction::JumpOffset { nnn, .. }) => Some(nnn),
+        _ => None
+    }).filter(|target| addresses.contains(target)).collect();
+
+    let label = |address :u16| if targets.contains(&address) {format!("L{:04X}", address)} else {format!("0x{:03X}", address)};
+
+    let mut listing = String::new();
+    for (address, bytes, instruction) in lines{
+        if targets.contains(&address){
+            listing.push_str(&format!("L{:04X}:\n", address));
+        }
+
+        let text = match instruction{
+            Some(instruction) => {
+                let long_operand = if bytes.len() == 4 {(bytes[2] as u16) << 8 | bytes[3] as u16} else {0};
+                format_instruction(&instruction, long_operand, &label)
+            },
+            None => {
+                let data :Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                format!("DB {}", data.join(", "))
+            }
+        };
+
+        let hex :Vec<String> = bytes.chunks(2).map(|word| word.iter().map(|byte| format!("{:02X}", byte)).collect()).collect();
+        listing.push_str(&format!("    {:<24}; {:04X}  {}\n", text, address, hex.join(" ")));
+    }
+    listing
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+
+
+//Errors raised while loading or running a rom. Execution errors carry the
+//address of the faulting instruction and the instruction itself
+#[derive(Debug, Clone, PartialEq)]
+pub enum Chip8Error{
+    InvalidOpcode { pc: u16, opcode: u16 },
+    StackUnderflow { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16, opcode: u16 },
+    MemoryOutOfBounds { pc: u16, opcode: u16, address: usize },
+    RomTooLarge { size: usize, capacity: usize },
+    RomRead { path: String, message: String }
+}
+
+impl fmt::Display for Chip8Error{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            Chip8Error::InvalidOpcode { pc, opcode } =>
+                write!(f, "Invalid opcode {:04X} at {:04X}", opcode, pc),
+            Chip8Error::StackUnderflow { pc, opcode } =>
+                write!(f, "Stack underflow: {:04X} at {:04X} returned with an empty stack", opcode, pc),
+            Chip8Error::StackOverflow { pc, opcode } =>
+                write!(f, "Stack overflow: {:04X} at {:04X} called with a full stack", opcode, pc),
+            Chip8Error::MemoryOutOfBounds { pc, opcode, address } =>
+                write!(f, "Memory out of bounds: {:04X} at {:04X} accessed address {:X}", opcode, pc, address),
+            Chip8Error::RomTooLarge { size, capacity } =>
+                write!(f, "Rom is {} bytes but only {} bytes are available", size, capacity),
+            Chip8Error::RomRead { path, message } =>
+                write!(f, "Can't read rom {}: {}", path, message)
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::io::{self, Write};
+use crate::Chip8;
+use crate::audio::AudioOutput;
+use crate::capture::VideoRecorder;
+use crate::error::Chip8Error;
+use crate::movie::MovieSession;
+use crate::scheduler::Scheduler;
+
+
+//How long a headless run lasts
+pub enum RunLength{
+    Frames(u32),
+    Cycles(u32)
+}
+
+//Key state change applied at the start of the given frame
+pub struct ScriptedKey{
+    pub frame :u32,
+    pub key :u8,
+    pub pressed :bool
+}
+
+pub struct Headless{
+    length :RunLength,
+    script :Vec<ScriptedKey>,
+}
+
+impl Headless{
+
+    pub fn new(length :RunLength) -> Headless{
+        Headless { length, script: vec![] }
+    }
+
+    pub fn press_key(&mut self, frame :u32, key :u8){
+        self.script.push(ScriptedKey { frame, key, pressed: true });
+    }
+
+    pub fn release_key(&mut self, frame :u32, key :u8){
+        self.script.push(ScriptedKey { frame, key, pressed: false });
+    }
+
+    //Script format, one event per line: <frame> <key 0-F> <down|up>
+    //Blank lines and lines starting with '#' are ignored
+    pub fn load_script(&mut self, path :&String) -> Result<(), String>{
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+
+        for (line_num, line) in source.lines().enumerate(){
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#'){
+                continue;
+            }
+
+            let parts :Vec<&str> = line.split_whitespace().collect();
+            if parts.len() != 3{
+                return Err(format!("{}:{}: expected '<frame> <key> <down|up>'", path, line_num+1));
+            }
+
+            let frame :u32 = parts[0].parse().map_err(|_| format!("{}:{}: invalid frame '{}'", path, line_num+1, parts[0]))?;
+            let key = u8::from_str_radix(parts[1], 16).ok().filter(|k| *k < 16)
+                .ok_or(format!("{}:{}: invalid key '{}'", path, line_num+1, parts[1]))?;
+            match parts[2]{
+                "down" => self.press_key(frame, key),
+                "up" => self.release_key(frame, key),
+                other => return Err(format!("{}:{}: expected 'down' or 'up', found '{}'", path, line_num+1, other))
+            }
+        }
+
+        Ok(())
+    }
+
+    //Runs the emulator without a window, one 60Hz frame after another as fast as
+    //possible. A cycle limit that ends mid-frame skips that frame's timer tick.
+    //A movie session sees the keypad after the script's events for the frame
+    pub fn run(&self, emulator :&mut Chip8, audio :&mut dyn AudioOutput, mut movie :Option<&mut MovieSession>, mut video :Option<&mut VideoRecorder>) -> Result<(), Chip8Error>{
+        let mut scheduler = Scheduler::new(emulator.get_cycle_speed().max(1) as u32);
+        let (frames, mut cycles_left) = match self.length{
+            RunLength::Frames(frames) => (frames, None),
+            RunLength::Cycles(cycles) => (u32::MAX, Some(cycles))
+        };
+
+        for frame in 0..frames{
+            if emulator.is_halted() || cycles_left == Some(0){
+                break;
+            }
+
+            for event in self.script.iter().filter(|event| event.frame == frame){
+                emulator.set_key(event.key, event.pressed);
+            }
+
+            let instructions = scheduler.peek_frame_instructions();
+            if let Some(left) = cycles_left.filter(|left| *left < instructions){
+                for _i in 0..left{
+                    if emulator.is_halted(){
+                        break;
+                    }
+                    emulator.cycle()?;
+                }
+                break;
+            }
+            scheduler.step(emulator, audio, false, movie.as_deref_mut(), video.as_deref_mut())?;
+            if let Some(left) = cycles_left.as_mut(){
+                *left -= instructions;
+            }
+        }
+        Ok(())
+    }
+}
+
+//Writes the framebuffer as a plain PBM (P1) image, readable by most image tools
+pub fn write_pbm<W: Write>(emulator :&Chip8, writer :&mut W) -> io::Result<()>{
+    let display = emulator.get_display();
+    let width = emulator.display_width();
+    let height = emulator.display_height();
+    writeln!(writer, "P1")?;
+    writeln!(writer, "{} {}", width, height)?;
+    for row in 0..height{
+        let line :Vec<&str> = (0..width).map(|col| if display[col + (width*row)] != 0 {"1"} else {"0"}).collect();
+        writeln!(writer, "{}", line.join(" "))?;
+    }
+    Ok(())
+}
+
+//Writes the framebuffer as an image if the path ends in .pbm and as text otherwise
+fn write_display_as<W: Write>(emulator :&Chip8, path :&str, writer :&mut W) -> io::Result<()>{
+    if path.ends_with(".pbm"){
+        write_pbm(emulator, writer)
+    }
+    else{
+        emulator.write_display(writer)
+    }
+}
+
+//Dumps the framebuffer to a file, as an image if the path ends in .pbm and as text otherwise
+pub fn dump_display(emulator :&Chip8, path :&String) -> Result<(), String>{
+    let mut file = fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    write_display_as(emulator, path, &mut file).map_err(|e| e.to_string())
+}
+
+//Compares the framebuffer with a dump written by dump_display. Trailing
+//whitespace is ignored so hand edited text dumps still match
+pub fn display_matches(emulator :&Chip8, path :&String) -> Result<bool, String>{
+    let expected = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut actual = vec![];
+    write_display_as(emulator, path, &mut actual).map_err(|e| e.to_string())?;
+    let actual = String::from_utf8_lossy(&actual);
+    Ok(actual.trim_end().lines().map(str::trim_end).eq(expected.trim_end().lines().map(str::trim_end)))
+}
@@ -1,58 +1,202 @@
 extern crate sdl2;
-use sdl2::event::Event;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::GameController;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use std::collections::HashMap;
 use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 use sdl2::rect::Rect;
-use crate::Chip8;
-use crate::chip8::Key;
+use rust8::Chip8;
+use rust8::audio::{AudioOutput, AudioSettings, Synth};
+use rust8::capture::{self, VideoRecorder};
+use rust8::palette::{self, Palette};
+use rust8::renderer::{Frame, Presenter, Renderer};
+use rust8::keymap::{KeyMap, Remap, KEYPAD_LAYOUT};
+use rust8::error::Chip8Error;
+use rust8::movie::MovieSession;
+use rust8::rewind::{Rewind, DEFAULT_REWIND_SECONDS};
+use rust8::scheduler::Scheduler;
 
 
 
+//Synth lives in the core library, SDL's callback trait can only be implemented on a local type
+struct SynthCallback(Synth);
+
+impl AudioCallback for SynthCallback{
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]){
+        self.0.fill(out);
+    }
+}
+
+//Plays the beep through an SDL audio device
+struct SdlAudio{
+    device: AudioDevice<SynthCallback>,
+    muted: bool
+}
+
+impl SdlAudio{
+
+    fn new(sdl_context :&sdl2::Sdl, settings :AudioSettings) -> Result<SdlAudio, String>{
+        let audio_subsystem = sdl_context.audio()?;
+        let desired_spec = AudioSpecDesired { freq: Some(44100), channels: Some(1), samples: None };
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| SynthCallback(Synth::new(settings, spec.freq as f32)))?;
+        device.resume();
+        Ok(SdlAudio { device, muted: settings.muted })
+    }
+}
+
+impl AudioOutput for SdlAudio{
+
+    fn update(&mut self, emulator :&Chip8){
+        self.device.lock().0.update(emulator);
+    }
+
+    fn set_muted(&mut self, muted :bool){
+        self.muted = muted;
+        self.device.lock().0.set_muted(muted);
+    }
+
+    fn is_muted(& self) -> bool{
+        self.muted
+    }
+}
+
+//Draws frames into the window: the display goes into a streaming texture stretched
+//over the whole window. A crashed rom keeps its last frame with a red border
+struct SdlRenderer<'a>{
+    canvas: Canvas<Window>,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    //Display sized, recreated whenever the rom switches between low and high-res
+    texture: Option<Texture<'a>>,
+    palette: Palette,
+    crashed: bool
+}
+
+impl SdlRenderer<'_>{
+
+    fn set_title(&mut self, title :&str) -> Result<(), String>{
+        self.canvas.window_mut().set_title(title).map_err(|e| e.to_string())
+    }
+}
+
+impl Renderer for SdlRenderer<'_>{
+
+    fn resize(&mut self, width :usize, height :usize) -> Result<(), String>{
+        let texture = self.texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+            .map_err(|e| e.to_string())?;
+        self.texture = Some(texture);
+        Ok(())
+    }
+
+    fn present(&mut self, frame :&Frame) -> Result<(), String>{
+        let texture = self.texture.as_mut().ok_or("Frame presented before the resolution was set")?;
+        let palette = self.palette;
+        texture.with_lock(None, |pixels, pitch| {
+            for (index, pixel) in frame.pixels.iter().enumerate(){
+                let offset = (index / frame.width)*pitch + (index % frame.width)*3;
+                pixels[offset..offset+3].copy_from_slice(&palette.color(*pixel));
+            }
+        })?;
+        self.canvas.copy(texture, None, None)?;
+
+        if self.crashed{
+            self.canvas.set_draw_color(Color::RGB(255,0,0));
+            let (width, height) = self.canvas.output_size()?;
+            for inset in 0..4{
+                self.canvas.draw_rect(Rect::new(inset, inset, width - 2*inset as u32, height - 2*inset as u32))?;
+            }
+        }
+        self.canvas.present();
+        Ok(())
+    }
+}
 
 pub struct Interface{
     window_title :String,
-    window_width :u32,
-    window_height :u32,
+    //Window pixels per CHIP-8 pixel at low-res, the window opens at 64x32 times this
     window_scale: u32,
+    //Save state slots are written to <prefix>.<slot>.state
+    save_state_prefix :String,
+    //Screenshots are written to <prefix>-<n>.png
+    screenshot_prefix :String,
+    audio_settings :AudioSettings,
+    palette :Palette,
+    key_map :KeyMap,
+    button_map :KeyMap,
+    //Seconds of history kept for rewinding, 0 turns it off
+    rewind_seconds :u32,
 }
 
 impl Interface{
 
-    pub fn new(title :String, width: u32, height: u32, scale: u32) -> Interface{
-            Interface { window_title: title, window_width: width, window_height: height, window_scale: scale}
+    pub fn new(title :String, scale: u32) -> Interface{
+            Interface { window_title: title, window_scale: scale, save_state_prefix: String::from("saves/RUST-8"), screenshot_prefix: String::from("screenshots/RUST-8"), audio_settings: AudioSettings::default(), palette: Palette::default(), key_map: KeyMap::default(), button_map: KeyMap::gamepad(), rewind_seconds: DEFAULT_REWIND_SECONDS}
     }
 
-    pub fn draw(&self, emulator :&Chip8, canvas :&mut Canvas<Window>) -> Result<(), String>{
-        canvas.set_draw_color(Color::RGB(0,0,0));
-        canvas.clear();
-        canvas.set_draw_color(Color::RGB(0,255,102));
-        
-        
-        let display = emulator.get_display();
-
-        for col in 0..self.window_width{
-            for row in 0..self.window_height{
-                if display[(col + (self.window_width * row)) as usize]{
-                    let rect = Rect::new((col*self.window_scale) as i32, (row*self.window_scale) as i32, self.window_scale, self.window_scale);
-                    canvas.draw_rect(rect)?;
-                    canvas.fill_rect(rect)?;
-                }
-            }
+    pub fn set_save_state_prefix(&mut self, prefix :String){
+        self.save_state_prefix = prefix;
+    }
+
+    pub fn set_screenshot_prefix(&mut self, prefix :String){
+        self.screenshot_prefix = prefix;
+    }
+
+    pub fn set_audio_settings(&mut self, settings :AudioSettings){
+        self.audio_settings = settings;
+    }
+
+    pub fn set_palette(&mut self, palette :Palette){
+        self.palette = palette;
+    }
+
+    pub fn set_key_map(&mut self, key_map :KeyMap){
+        self.key_map = key_map;
+    }
+
+    pub fn set_button_map(&mut self, button_map :KeyMap){
+        self.button_map = button_map;
+    }
+
+    pub fn set_rewind_seconds(&mut self, seconds :u32){
+        self.rewind_seconds = seconds;
+    }
+
+    fn save_state_path(&self, slot :u32) -> String{
+        format!("{}.{}.state", self.save_state_prefix, slot)
+    }
+
+    fn save_state(&self, emulator :&Chip8, slot :u32) -> Result<(), String>{
+        let path = self.save_state_path(slot);
+        if let Some(directory) = std::path::Path::new(&path).parent(){
+            std::fs::create_dir_all(directory).map_err(|e| e.to_string())?;
         }
-        canvas.present();
+        let mut file = std::fs::File::create(&path).map_err(|e| format!("{}: {}", path, e))?;
+        emulator.save_state(&mut file).map_err(|e| format!("{}: {}", path, e))?;
+        println!("Saved state to slot {} ({})", slot, path);
         Ok(())
     }
 
-    pub fn window_loop(&self, emulator :&mut Chip8) -> Result<(), String>{
+    fn load_state(&self, emulator :&mut Chip8, slot :u32) -> Result<(), String>{
+        let path = self.save_state_path(slot);
+        let mut file = std::fs::File::open(&path).map_err(|e| format!("{}: {}", path, e))?;
+        emulator.load_state(&mut std::io::BufReader::new(&mut file)).map_err(|e| format!("{}: {}", path, e))?;
+        println!("Loaded state from slot {} ({})", slot, path);
+        Ok(())
+    }
+
+    //Runs until the rom exits or the window is closed, frames go through Scheduler::step
+    pub fn window_loop(&self, emulator :&mut Chip8, mut movie :Option<&mut MovieSession>, mut video :Option<&mut VideoRecorder>) -> Result<(), String>{
         let sdl_context = sdl2::init()?;
         let video_subsystem = sdl_context.video()?;
 
         let window = video_subsystem
-            .window(&self.window_title, self.window_width*self.window_scale, self.window_height*self.window_scale)
+            .window(&self.window_title, 64*self.window_scale, 32*self.window_scale)
             .position_centered()
             .opengl()
             .build()
@@ -61,84 +205,247 @@ impl Interface{
 
         
         
-        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().present_vsync().build().map_err(|e| e.to_string())?;
+        let texture_creator = canvas.texture_creator();
+        let mut renderer = SdlRenderer { canvas, texture_creator: &texture_creator, texture: None, palette: self.palette, crashed: false };
+        let mut presenter = Presenter::new();
+        let mut redraw = true;
 
         let mut event = sdl_context.event_pump()?;
 
-        let mut timers_total_elapsed = Duration::new(0,0);        
-        let  cycle_time = Duration::new(0,0);
+        //A machine without sound still runs the rom, the beep just isn't heard
+        let mut audio :Box<dyn AudioOutput> = match SdlAudio::new(&sdl_context, self.audio_settings){
+            Ok(audio) => Box::new(audio),
+            Err(error) => {
+                eprintln!("Can't open an audio device, running without sound: {}", error);
+                Box::new(Synth::new(self.audio_settings, 44100.0))
+            }
+        };
+
+        //Controllers are opened as they are plugged in, SDL also reports the ones
+        //already connected at startup as added. Keyed by instance id
+        let controller_subsystem = sdl_context.game_controller()?;
+        let mut controllers :HashMap<u32, GameController> = HashMap::new();
+
+        let mut scheduler = Scheduler::new(emulator.get_cycle_speed().max(1) as u32);
+        let mut last_time = Instant::now();
+        let mut crash :Option<Chip8Error> = None;
+        let mut save_slot :u32 = 0;
+        let mut key_map = self.key_map.clone();
+        //New key map being recorded and the next keypad key to ask for
+        let mut remap :Option<Remap> = None;
+        //Rewinding would break the frame by frame input of a movie
+        let mut rewind = Rewind::new(if movie.is_some() {0} else {self.rewind_seconds});
+        let mut rewinding = false;
+        rewind.push(emulator);
         
 
         'running: loop {
-            let cycle_curr = Instant::now();
-            let iterations = emulator.get_cycle_speed()/60;
-            for _i in 0..iterations{
-                emulator.cycle();
-                self.draw(emulator, &mut canvas).expect("Error drawing to screen");
+            if emulator.is_halted(){
+                break 'running;
             }
-            
 
-            let curr_time = Instant::now();
-            timers_total_elapsed += curr_time - cycle_curr;
-
-            if timers_total_elapsed > Duration::from_secs(1/60){
-                emulator.decrement_timers();
-                timers_total_elapsed -= Duration::from_secs(1/60);
+            let now = Instant::now();
+            let frames = scheduler.advance(now - last_time);
+            last_time = now;
+            for _i in 0..frames{
+                //While the rewind key is held every frame steps back instead of running
+                if rewinding{
+                    if rewind.step_back(emulator) && crash.take().is_some(){
+                        renderer.set_title(&self.window_title)?;
+                        renderer.crashed = false;
+                    }
+                    audio.update(emulator);
+                    //Every frame is recorded, rewinding included
+                    if let Some(recorder) = video.as_deref_mut(){
+                        recorder.record(&Frame::of(emulator));
+                    }
+                }
+                else{
+                    match scheduler.step(emulator, audio.as_mut(), crash.is_some(), movie.as_deref_mut(), video.as_deref_mut()){
+                        Err(error) => {
+                            eprintln!("{}", error);
+                            renderer.set_title(&format!("{} - crashed: {}", self.window_title, error))?;
+                            crash = Some(error);
+                            renderer.crashed = true;
+                            redraw = true;
+                        },
+                        Ok(verdict) => {
+                            if crash.is_none(){
+                                rewind.push(emulator);
+                            }
+                            //The replay has ended, from here on the keys are the player's
+                            match verdict{
+                                Some(Ok(message)) => println!("{}", message),
+                                Some(Err(message)) => eprintln!("{}", message),
+                                None => ()
+                            }
+                        }
+                    }
+                }
             }
-            
+
+            //Only present when something changed, present blocks until vsync
+            let presented = presenter.update(emulator, &mut renderer, redraw)?;
+            redraw = false;
+
             for event in event.poll_iter() {
+                //While remapping, key presses go to the new key map instead of the rom
+                if let Some(remapping) = &mut remap{
+                    match event{
+                        Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                            remap = None;
+                            println!("Key remapping cancelled");
+                            renderer.set_title(&self.window_title)?;
+                        },
+                        Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => {
+                            if let Some(new_map) = remapping.press(scancode.name()){
+                                key_map = new_map;
+                                remap = None;
+                                println!("Keys remapped, to keep this layout add it to a config profile:\n{}", key_map.to_config());
+                                renderer.set_title(&self.window_title)?;
+                            }
+                            else{
+                                let prompt = format!("{} - press the key for {:X} (Esc cancels)", self.window_title, remapping.next_key());
+                                renderer.set_title(&prompt)?;
+                            }
+                        },
+                        Event::Quit { .. } => break 'running,
+                        _ => ()
+                    }
+                    continue;
+                }
+
                 match event {
                     Event::Quit { .. }
                     | Event::KeyDown {
                         keycode: Some(Keycode::Escape),
                         ..
                     } => break 'running,
-                    Event::KeyDown { scancode: Some(scancode), .. } =>{
-                        match scancode{
-                            sdl2::keyboard::Scancode::Num1 => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::Num2 => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::Num3 => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::Num4 => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::Q => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::W => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::E => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::R => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::A => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::S => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::D => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::F => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::Z => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::X => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::C => emulator.read_input(scancode.name(), Key::Pressed),
-                            sdl2::keyboard::Scancode::V => emulator.read_input(scancode.name(), Key::Pressed),
-                            _ => println!("UNSUPPORTED KEY PRESSED")
+                    Event::Window { win_event: WindowEvent::Exposed, .. } => redraw = true,
+                    //Keys bound to the keypad go to the rom even if they are also hotkeys, so
+                    //a profile can use M, P or Backspace. Esc always quits
+                    Event::KeyDown { scancode: Some(scancode), .. } if key_map.get(scancode.name()).is_some() =>{
+                        if let Some(key) = key_map.get(scancode.name()){
+                            emulator.set_key(key, true);
+                        }
+                    },
+                    Event::KeyUp { scancode: Some(scancode), .. } if key_map.get(scancode.name()).is_some() =>{
+                        if let Some(key) = key_map.get(scancode.name()){
+                            emulator.set_key(key, false);
                         }
                     },
-                    Event::KeyUp { scancode: Some(scancode), .. } =>{
-                        match scancode{
-                            sdl2::keyboard::Scancode::Num1 => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::Num2 => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::Num3 => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::Num4 => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::Q => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::W => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::E => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::R => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::A => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::S => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::D => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::F => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::Z => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::X => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::C => emulator.read_input(scancode.name(), Key::Released),
-                            sdl2::keyboard::Scancode::V => emulator.read_input(scancode.name(), Key::Released),
-                            _ => println!("UNSUPPORTED KEY RELEASED")
+                    //Save states: F5 saves, F9 loads, F6/F7 select the slot
+                    Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } =>{
+                        if let Err(message) = self.save_state(emulator, save_slot){
+                            eprintln!("Error saving state: {}", message);
+                        }
+                    },
+                    //Loading a state would break the frame by frame input of a movie
+                    Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } if movie.is_some() =>{
+                        eprintln!("Can't load a state while recording or replaying a movie");
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::F9), repeat: false, .. } =>{
+                        match self.load_state(emulator, save_slot){
+                            Ok(()) => {
+                                crash = None;
+                                renderer.crashed = false;
+                                renderer.set_title(&self.window_title)?;
+                            },
+                            Err(message) => eprintln!("Error loading state: {}", message)
+                        }
+                    },
+                    //Backspace rewinds for as long as it is held
+                    Event::KeyDown { keycode: Some(Keycode::Backspace), repeat: false, .. } =>{
+                        if rewind.is_enabled(){
+                            rewinding = true;
+                        }
+                        else{
+                            println!("Rewind is off{}", if movie.is_some() {" while recording or replaying a movie"} else {""});
+                        }
+                    },
+                    Event::KeyUp { keycode: Some(Keycode::Backspace), .. } if rewinding =>{
+                        rewinding = false;
+                        //The keys in the restored state aren't the ones the player is holding now
+                        for key in 0..16{
+                            emulator.set_key(key, false);
+                        }
+                        println!("Rewind stopped, {:.1}s of history left", rewind.seconds());
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::M), repeat: false, .. } =>{
+                        audio.set_muted(!audio.is_muted());
+                        println!("Sound {}", if audio.is_muted() {"muted"} else {"unmuted"});
+                    },
+                    //Cycle through the preset palettes
+                    Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } =>{
+                        let (name, preset) = palette::next_preset(&renderer.palette);
+                        renderer.palette = preset;
+                        if let Some(recorder) = video.as_deref_mut(){
+                            recorder.set_palette(preset);
+                        }
+                        redraw = true;
+                        println!("Palette {}", name);
+                    },
+                    //Screenshot of the display as the window shows it
+                    Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } =>{
+                        match capture::numbered_screenshot(emulator, &self.screenshot_prefix, renderer.palette, self.window_scale){
+                            Ok(path) => println!("Saved screenshot to {}", path),
+                            Err(message) => eprintln!("Error saving screenshot: {}", message)
+                        }
+                    },
+                    //Remap the keypad by pressing the new key for every keypad key in turn
+                    Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } =>{
+                        for key in KEYPAD_LAYOUT{
+                            emulator.set_key(key, false);
+                        }
+                        let remapping = Remap::start();
+                        println!("Remapping keys, press the key for each keypad key in the order 123C 456D 789E A0BF");
+                        let prompt = format!("{} - press the key for {:X} (Esc cancels)", self.window_title, remapping.next_key());
+                        remap = Some(remapping);
+                        renderer.set_title(&prompt)?;
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::F6), .. } =>{
+                        save_slot = (save_slot + 9) % 10;
+                        println!("Save slot {}", save_slot);
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::F7), .. } =>{
+                        save_slot = (save_slot + 1) % 10;
+                        println!("Save slot {}", save_slot);
+                    },
+                    Event::ControllerDeviceAdded { which, .. } =>{
+                        match controller_subsystem.open(which){
+                            Ok(controller) => {
+                                println!("Controller connected: {}", controller.name());
+                                controllers.insert(controller.instance_id(), controller);
+                            },
+                            Err(error) => eprintln!("Can't open controller {}: {}", which, error)
+                        }
+                    },
+                    Event::ControllerDeviceRemoved { which, .. } =>{
+                        if let Some(controller) = controllers.remove(&which){
+                            println!("Controller disconnected: {}", controller.name());
+                            //Don't leave keys held by a controller that is gone
+                            for key in KEYPAD_LAYOUT{
+                                emulator.set_key(key, false);
+                            }
+                        }
+                    },
+                    Event::ControllerButtonDown { button, .. } =>{
+                        if let Some(key) = self.button_map.get(&button.string()){
+                            emulator.set_key(key, true);
+                        }
+                    },
+                    Event::ControllerButtonUp { button, .. } =>{
+                        if let Some(key) = self.button_map.get(&button.string()){
+                            emulator.set_key(key, false);
                         }
                     },
                     _ => {}
                 }
             }
-            sleep(Duration::from_micros(16666)-cycle_time);   
+            if !presented{
+                sleep(scheduler.time_until_next_frame());
+            }
         }
         
         Ok(())
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+
+//Keypad keys in the order they sit on the COSMAC VIP hex keypad, row by row
+pub const KEYPAD_LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF
+];
+
+//Host keys of the default layout, the 4x4 block under 1234 on a QWERTY keyboard.
+//The window passes physical key names, so there this block is in the same place on
+//AZERTY or Dvorak. The terminal only gets the character a key types, so it follows the layout
+const DEFAULT_KEYS: [&str; 16] = [
+    "1", "2", "3", "4",
+    "Q", "W", "E", "R",
+    "A", "S", "D", "F",
+    "Z", "X", "C", "V"
+];
+
+//Game controller buttons (SDL names) of the default pad layout. The D-pad covers
+//the 2/4/6/8 arrows most games use, the face and shoulder buttons the keys around them
+const DEFAULT_BUTTONS: [(&str, u8); 10] = [
+    ("dpup", 0x2), ("dpdown", 0x8), ("dpleft", 0x4), ("dpright", 0x6),
+    ("a", 0x5), ("b", 0x0), ("x", 0x7), ("y", 0x9),
+    ("leftshoulder", 0x1), ("rightshoulder", 0x3)
+];
+
+//Maps host key names to the 16 CHIP-8 keys. Names are whatever the frontend
+//reports (SDL scancode or controller button names for the window, typed characters
+//for the terminal) and are matched ignoring case
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyMap{
+    bindings: BTreeMap<String, u8>
+}
+
+impl KeyMap{
+
+    pub fn empty() -> KeyMap{
+        KeyMap { bindings: BTreeMap::new() }
+    }
+
+    //Default layout for game controller buttons
+    pub fn gamepad() -> KeyMap{
+        let mut key_map = KeyMap::empty();
+        for (button, key) in DEFAULT_BUTTONS{
+            key_map.bind(button, key);
+        }
+        key_map
+    }
+
+    //Binds a host key, replacing whatever it was bound to before
+    pub fn bind(&mut self, host_key :&str, key :u8){
+        self.bindings.insert(host_key.to_lowercase(), key & 0xF);
+    }
+
+    //Replaces every binding of a keypad key with the given host keys
+    pub fn rebind(&mut self, key :u8, host_keys :&[String]){
+        self.bindings.retain(|_, bound| *bound != key);
+        for host_key in host_keys{
+            self.bind(host_key, key);
+        }
+    }
+
+    pub fn get(& self, host_key :&str) -> Option<u8>{
+        self.bindings.get(&host_key.to_lowercase()).copied()
+    }
+
+    //Host keys bound to a keypad key
+    pub fn host_keys(& self, key :u8) -> Vec<&str>{
+        self.bindings.iter().filter(|(_, bound)| **bound == key).map(|(host_key, _)| host_key.as_str()).collect()
+    }
+
+    //The bindings as a config 'keys' table, keypad keys in hex
+    pub fn to_config(& self) -> String{
+        let entries :Vec<String> = KEYPAD_LAYOUT.iter().filter_map(|key| {
+            let host_keys :Vec<String> = self.host_keys(*key).iter().map(|host_key| format!("\"{}\"", host_key)).collect();
+            match host_keys.len(){
+                0 => None,
+                1 => Some(format!("\"{:X}\" = {}", key, host_keys[0])),
+                _ => Some(format!("\"{:X}\" = [{}]", key, host_keys.join(", ")))
+            }
+        }).collect();
+        format!("keys = {{ {} }}", entries.join(", "))
+    }
+}
+
+//A new key map built from one key press per keypad key, in KEYPAD_LAYOUT order
+pub struct Remap{
+    key_map: KeyMap,
+    position: usize
+}
+
+impl Remap{
+
+    pub fn start() -> Remap{
+        Remap { key_map: KeyMap::empty(), position: 0 }
+    }
+
+    //Keypad key the next press is bound to
+    pub fn next_key(& self) -> u8{
+        KEYPAD_LAYOUT[self.position]
+    }
+
+    //Binds the host key to the next keypad key, returns the finished map after the last one
+    pub fn press(&mut self, host_key :&str) -> Option<KeyMap>{
+        self.key_map.bind(host_key, self.next_key());
+        self.position += 1;
+        (self.position == KEYPAD_LAYOUT.len()).then(|| std::mem::replace(&mut self.key_map, KeyMap::empty()))
+    }
+}
+
+impl Default for KeyMap{
+    fn default() -> KeyMap{
+        let mut key_map = KeyMap::empty();
+        for (host_key, key) in DEFAULT_KEYS.iter().zip(KEYPAD_LAYOUT){
+            key_map.bind(host_key, key);
+        }
+        key_map
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn default_layout_is_the_block_under_1234(){
+        let key_map = KeyMap::default();
+        assert_eq!((key_map.get("1"), key_map.get("4"), key_map.get("q"), key_map.get("V")), (Some(0x1), Some(0xC), Some(0x4), Some(0xF)));
+        assert_eq!(key_map.get("5"), None);
+        assert_eq!(KeyMap::gamepad().get("DPUP"), Some(0x2));
+    }
+
+    #[test]
+    fn bindings_replace_earlier_ones(){
+        let mut key_map = KeyMap::default();
+        key_map.rebind(0x5, &[String::from("Up"), String::from("K")]);
+        assert_eq!((key_map.get("W"), key_map.get("up"), key_map.get("k")), (None, Some(0x5), Some(0x5)));
+        //A host key presses one keypad key at a time
+        key_map.bind("K", 0x6);
+        assert_eq!((key_map.get("k"), key_map.host_keys(0x5)), (Some(0x6), vec!["up"]));
+        assert!(key_map.to_config().starts_with("keys = { \"1\" = \"1\", \"2\" = \"2\", \"3\" = \"3\", \"C\" = \"4\", \"4\" = \"q\", \"5\" = \"up\", \"6\" = [\"e\", \"k\"]"));
+    }
+
+    #[test]
+    fn remapping_binds_the_keypad_in_layout_order(){
+        let mut remap = Remap::start();
+        let host_keys = ["A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O"];
+        for host_key in host_keys{
+            assert!(remap.press(host_key).is_none());
+        }
+        assert_eq!(remap.next_key(), 0xF);
+        let key_map = remap.press("P").unwrap();
+        assert_eq!((key_map.get("a"), key_map.get("d"), key_map.get("n"), key_map.get("p")), (Some(0x1), Some(0xC), Some(0x0), Some(0xF)));
+        //Nothing of the old layout is left
+        assert_eq!(key_map.get("1"), None);
+    }
+}
@@ -0,0 +1,32 @@
+//The RUST-8 interpreter core: the machine, its timing and everything around it
+//that doesn't need a window. The rust-8 binary adds the command line, config
+//files and the frontends on top of this
+pub mod asm;
+pub mod audio;
+pub mod capture;
+pub mod chip8;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod headless;
+pub mod keymap;
+pub mod movie;
+pub mod opcode;
+pub mod palette;
+pub mod quirks;
+pub mod renderer;
+pub mod rewind;
+pub mod rng;
+pub mod scheduler;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use chip8::Chip8;
+pub use error::Chip8Error;
+pub use quirks::Quirks;
+
+
+//Lowercase hex SHA-1, used to identify roms and machine states
+pub fn sha1_hex(data :&[u8]) -> String{
+    sha1_smol::Sha1::from(data).digest().to_string()
+}
@@ -1,27 +1,229 @@
-
-mod chip8;
+mod cli;
+mod config;
+mod debug_console;
+#[cfg(feature = "sdl-frontend")]
 mod interface;
+#[cfg(feature = "tui-frontend")]
+mod terminal;
 
 
-use chip8::Chip8;
-use crate::interface::Interface;
+use rust8::{asm, capture, disasm, headless, Chip8};
+use rust8::audio::{AudioOutput, BeepRecorder};
+use rust8::capture::VideoRecorder;
+use rust8::headless::{Headless, RunLength};
+use rust8::movie::{Movie, MovieSession};
+use rust8::renderer::{PngRenderer, Presenter};
+use rust8::rng::XorShift;
+use crate::cli::{Command, RunOptions};
+use crate::config::ConfigSet;
 
 
-fn main() -> Result<(), String>{
-    let mut args = std::env::args();    
-    let rom_name = args.nth(1).unwrap();
-    let rom_folder_path = String::from("./roms/");
-    let rom_path = rom_folder_path+&rom_name;
-    let cycle_speed_arg = args.next().unwrap();
-    let cycle_speed :i32 = cycle_speed_arg.parse().expect("Can't parse cycle speed, enter an integer");
+//rust-8 disasm <rom>
+fn disassemble(rom_path :&String, origin :u16) -> Result<(), String>{
+    let rom = std::fs::read(rom_path).map_err(|e| format!("Can't read rom {}: {}", rom_path, e))?;
+    print!("{}", disasm::disassemble(&rom, origin));
+    Ok(())
+}
+
+//rust-8 asm <source> <output>
+fn assemble(source_path :&String, output_path :&String) -> Result<(), String>{
+    let source = std::fs::read_to_string(source_path).map_err(|e| format!("Can't read {}: {}", source_path, e))?;
+    let rom = asm::assemble(&source).map_err(|e| format!("{}:{}", source_path, e))?;
+    std::fs::write(output_path, &rom).map_err(|e| format!("Can't write {}: {}", output_path, e))?;
+    println!("Assembled {} bytes to {}", rom.len(), output_path);
+    Ok(())
+}
 
-    let mut chip = Chip8::new(cycle_speed);
-    chip.load_rom(&rom_path);
+//Recording needs a known seed, so one is picked here unless --seed gave one
+fn load(options :&mut RunOptions) -> Result<Chip8, String>{
+    if options.record_path.is_some(){
+        options.seed.get_or_insert_with(rand::random::<u64>);
+    }
+    let mut chip = Chip8::new(options.speed as i32, options.quirks);
+    chip.load_rom(&options.rom_path).map_err(|e| e.to_string())?;
+    //Headless runs keep quiet, their stdout is the framebuffer
+    if options.headless.is_none(){
+        eprintln!("Loaded rom {}", options.rom_path);
+    }
     chip.load_font();
+    if let Some(seed) = options.seed{
+        chip.set_rng(Box::new(XorShift::new(seed)));
+    }
+    Ok(chip)
+}
+
+fn movie_session(options :&mut RunOptions) -> Result<Option<MovieSession>, String>{
+    if let Some(movie) = options.replay.take(){
+        return Ok(Some(MovieSession::replay(movie)));
+    }
+    match options.record_path.take(){
+        Some(path) => {
+            let rom = std::fs::read(&options.rom_path).map_err(|e| format!("Can't read rom {}: {}", options.rom_path, e))?;
+            let movie = Movie::new(rust8::sha1_hex(&rom), options.seed.unwrap_or(0), options.speed, options.quirks);
+            Ok(Some(MovieSession::record(movie, path)))
+        },
+        None => Ok(None)
+    }
+}
+
+//Writes a recording or reports a replay's verdict
+fn finish_movie(movie :Option<MovieSession>, chip :&mut Chip8) -> Result<(), String>{
+    if let Some(session) = movie{
+        eprintln!("{}", session.finish(chip)?);
+    }
+    Ok(())
+}
+
+fn video_recorder(options :&RunOptions) -> Result<Option<VideoRecorder>, String>{
+    options.video_target.as_ref().map(|target| VideoRecorder::create(target, options.palette, options.scale)).transpose()
+}
+
+//Writes the --screenshot and finishes the --video, also after a crash since they usually show it
+fn finish_capture(chip :&Chip8, options :&RunOptions, video :Option<VideoRecorder>) -> Result<(), String>{
+    if let Some(path) = &options.screenshot_path{
+        capture::screenshot(chip, path, options.palette, options.scale)?;
+        eprintln!("Saved screenshot to {}", path);
+    }
+    if let Some(recorder) = video{
+        eprintln!("{}", recorder.finish()?);
+    }
+    Ok(())
+}
+
+//A .png path gets an image at the configured scale and palette, anything else the text dump
+fn dump(chip :&mut Chip8, path :&String, options :&RunOptions) -> Result<(), String>{
+    if path.ends_with(".png"){
+        let mut png = PngRenderer::new(path.clone(), options.palette, options.scale);
+        return Presenter::new().update(chip, &mut png, true).map(|_| ());
+    }
+    headless::dump_display(chip, path)
+}
+
+//Runs without a window, then dumps or prints the framebuffer. It is dumped even
+//if the rom crashed since it usually shows how far it got
+fn run_headless(chip :&mut Chip8, options :&RunOptions, length :RunLength, movie :Option<&mut MovieSession>, video :Option<&mut VideoRecorder>) -> Result<(), String>{
+    let mut headless = Headless::new(length);
+    if let Some(script) = &options.input_script{
+        headless.load_script(script)?;
+    }
+    let mut beeps = BeepRecorder::new();
+    beeps.set_muted(options.audio.muted);
+    let result = headless.run(chip, &mut beeps, movie, video);
 
-    let interface = Interface::new(String::from("RUST-8"), 64, 32, 25);
-       
-    let _ = interface.window_loop(&mut chip);
+    if let Some(path) = &options.beep_log{
+        let mut file = std::fs::File::create(path).map_err(|e| format!("Can't write {}: {}", path, e))?;
+        beeps.write_log(&mut file).map_err(|e| format!("Can't write {}: {}", path, e))?;
+    }
 
+    match &options.dump_path{
+        Some(path) => dump(chip, path, options)?,
+        None => chip.debug_display()
+    }
+    result.map_err(|e| e.to_string())
+}
+
+//rust-8 [run] <rom>
+fn run(mut options :RunOptions) -> Result<(), String>{
+    let mut chip = load(&mut options)?;
+    let mut movie = movie_session(&mut options)?;
+
+    if options.debug{
+        return debug_console::run(&mut chip);
+    }
+
+    let mut video = video_recorder(&options)?;
+    let result = if let Some(length) = options.headless.take(){
+        run_headless(&mut chip, &options, length, movie.as_mut(), video.as_mut())
+    }
+    else if options.tui{
+        run_terminal(&mut chip, &options, movie.as_mut(), video.as_mut())
+    }
+    else{
+        run_window(&mut chip, &options, movie.as_mut(), video.as_mut())
+    };
+    result.and(finish_capture(&chip, &options, video)).and(finish_movie(movie, &mut chip))
+}
+
+//File name of the rom, the frontends name their window, saves and screenshots after it
+#[cfg(any(feature = "sdl-frontend", feature = "tui-frontend"))]
+fn rom_name(options :&RunOptions) -> String{
+    std::path::Path::new(&options.rom_path).file_name().map_or(String::from("rom"), |name| name.to_string_lossy().into_owned())
+}
+
+#[cfg(feature = "sdl-frontend")]
+fn run_window(chip :&mut Chip8, options :&RunOptions, movie :Option<&mut MovieSession>, video :Option<&mut VideoRecorder>) -> Result<(), String>{
+    let rom_name = rom_name(options);
+    let mut interface = interface::Interface::new(String::from("RUST-8"), options.scale);
+    interface.set_save_state_prefix(format!("saves/{}", rom_name));
+    interface.set_screenshot_prefix(format!("screenshots/{}", rom_name));
+    interface.set_audio_settings(options.audio);
+    interface.set_palette(options.palette);
+    interface.set_key_map(options.key_map.clone());
+    interface.set_button_map(options.button_map.clone());
+    interface.set_rewind_seconds(options.rewind_seconds);
+    interface.window_loop(chip, movie, video)
+}
+
+#[cfg(not(feature = "sdl-frontend"))]
+fn run_window(_chip :&mut Chip8, _options :&RunOptions, _movie :Option<&mut MovieSession>, _video :Option<&mut VideoRecorder>) -> Result<(), String>{
+    Err(String::from("This build has no window, rebuild with the sdl-frontend feature or run with --tui or --headless"))
+}
+
+#[cfg(feature = "tui-frontend")]
+fn run_terminal(chip :&mut Chip8, options :&RunOptions, movie :Option<&mut MovieSession>, video :Option<&mut VideoRecorder>) -> Result<(), String>{
+    let rom_name = rom_name(options);
+    let mut terminal = terminal::Terminal::new(format!("RUST-8 {}", rom_name));
+    terminal.set_screenshots(format!("screenshots/{}", rom_name), options.scale);
+    terminal.set_palette(options.palette);
+    terminal.set_key_map(options.key_map.clone());
+    terminal.terminal_loop(chip, movie, video)
+}
+
+#[cfg(not(feature = "tui-frontend"))]
+fn run_terminal(_chip :&mut Chip8, _options :&RunOptions, _movie :Option<&mut MovieSession>, _video :Option<&mut VideoRecorder>) -> Result<(), String>{
+    Err(String::from("This build has no terminal frontend, rebuild with the tui-frontend feature"))
+}
+
+//rust-8 test <rom> --expect <dump>
+fn test(mut options :RunOptions, expected_path :&String) -> Result<(), String>{
+    let mut chip = load(&mut options)?;
+    let mut movie = movie_session(&mut options)?;
+    let mut video = video_recorder(&options)?;
+    let length = options.headless.take().unwrap_or(RunLength::Frames(cli::DEFAULT_HEADLESS_FRAMES));
+    let mut headless = Headless::new(length);
+    if let Some(script) = &options.input_script{
+        headless.load_script(script)?;
+    }
+    headless.run(&mut chip, &mut BeepRecorder::new(), movie.as_mut(), video.as_mut()).map_err(|e| format!("{}: {}", options.rom_path, e))?;
+    finish_movie(movie, &mut chip)?;
+    finish_capture(&chip, &options, video)?;
+
+    if let Some(path) = &options.dump_path{
+        dump(&mut chip, path, &options)?;
+    }
+    if !headless::display_matches(&chip, expected_path)?{
+        chip.debug_display();
+        return Err(format!("FAIL {}: framebuffer doesn't match {}", options.rom_path, expected_path));
+    }
+    println!("PASS {}", options.rom_path);
     Ok(())
 }
+
+fn main(){
+    let result = match cli::parse(std::env::args().skip(1), ConfigSet::load){
+        Ok(Command::Run(options)) => run(options),
+        Ok(Command::Test { options, expected_path }) => test(options, &expected_path),
+        Ok(Command::Disasm { rom_path, origin }) => disassemble(&rom_path, origin),
+        Ok(Command::Asm { source_path, output_path }) => assemble(&source_path, &output_path),
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        },
+        Err(message) => Err(message)
+    };
+
+    if let Err(message) = result{
+        eprintln!("rust-8: {}", message);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,316 @@
+use std::fs;
+use std::io::{self, Write};
+use crate::Chip8;
+use crate::sha1_hex;
+use crate::quirks::Quirks;
+
+
+const MOVIE_VERSION: u32 = 1;
+//A week at 60Hz, a longer movie is a corrupt file and would only exhaust memory
+const MAX_FRAMES: usize = 7*24*60*60*60;
+
+//A recorded session: everything needed to start the machine the same way plus
+//the keypad state at the start of every frame. Replaying it with the same rom
+//drives Chip8::cycle through exactly the same states
+pub struct Movie{
+    pub rom_sha1: String,
+    pub seed: u64,
+    pub speed: u32,
+    pub quirks: Quirks,
+    //Keypad bitmask per frame
+    frames: Vec<u16>,
+    //SHA-1 of the save state after the last frame, checked at the end of a replay
+    final_state: Option<String>
+}
+
+//Text file, a header followed by '<keypad mask> <frames>' runs:
+//
+//  rust-8 movie 1
+//  rom <sha1>
+//  seed <n>
+//  speed <n>
+//  quirks shift_uses_vy=0 jump_uses_vx=1 ...
+//  final <sha1>
+//  frames
+//  0000 120
+//  0010 6
+impl Movie{
+
+    pub fn new(rom_sha1 :String, seed :u64, speed :u32, quirks :Quirks) -> Movie{
+        Movie { rom_sha1, seed, speed, quirks, frames: vec![], final_state: None }
+    }
+
+    pub fn len(& self) -> usize{
+        self.frames.len()
+    }
+
+    pub fn is_empty(& self) -> bool{
+        self.frames.is_empty()
+    }
+
+    pub fn write<W: Write>(& self, writer :&mut W) -> io::Result<()>{
+        writeln!(writer, "rust-8 movie {}", MOVIE_VERSION)?;
+        writeln!(writer, "rom {}", self.rom_sha1)?;
+        writeln!(writer, "seed {}", self.seed)?;
+        writeln!(writer, "speed {}", self.speed)?;
+        let quirks :Vec<String> = self.quirks.flags().iter().map(|(name, value)| format!("{}={}", name, *value as u8)).collect();
+        writeln!(writer, "quirks {}", quirks.join(" "))?;
+        if let Some(final_state) = &self.final_state{
+            writeln!(writer, "final {}", final_state)?;
+        }
+        writeln!(writer, "frames")?;
+
+        let mut frames = self.frames.iter().peekable();
+        while let Some(keys) = frames.next(){
+            let mut count = 1;
+            while frames.next_if_eq(&keys).is_some(){
+                count += 1;
+            }
+            writeln!(writer, "{:04X} {}", keys, count)?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(text :&str) -> Result<Movie, String>{
+        let mut lines = text.lines().enumerate().map(|(line_num, line)| (line_num+1, line.trim())).filter(|(_, line)| !line.is_empty());
+        let error = |line_num :usize, message :&str| format!("line {}: {}", line_num, message);
+
+        match lines.next(){
+            Some((_, header)) if header == format!("rust-8 movie {}", MOVIE_VERSION) => (),
+            Some((line_num, _)) => return Err(error(line_num, "not a rust-8 movie or an unsupported version")),
+            None => return Err(String::from("empty movie"))
+        }
+
+        let mut movie = Movie::new(String::new(), 0, 0, Quirks::default());
+        let mut has_frames = false;
+        for (line_num, line) in lines.by_ref(){
+            let (field, value) = line.split_once(' ').unwrap_or((line, ""));
+            match field{
+                "rom" => movie.rom_sha1 = value.to_lowercase(),
+                "seed" => movie.seed = value.parse().map_err(|_| error(line_num, "invalid seed"))?,
+                "speed" => movie.speed = value.parse().map_err(|_| error(line_num, "invalid speed"))?,
+                "quirks" => for flag in value.split_whitespace(){
+                    let valid = match flag.split_once('='){
+                        Some((name, "0")) => movie.quirks.set_flag(name, false),
+                        Some((name, "1")) => movie.quirks.set_flag(name, true),
+                        _ => false
+                    };
+                    if !valid{
+                        return Err(error(line_num, &format!("invalid quirk '{}'", flag)));
+                    }
+                },
+                "final" => movie.final_state = Some(value.to_lowercase()),
+                "frames" => {
+                    has_frames = true;
+                    break;
+                },
+                _ => return Err(error(line_num, &format!("unknown field '{}'", field)))
+            }
+        }
+        if !has_frames{
+            return Err(String::from("missing 'frames' section"));
+        }
+
+        //The runs are checked before any frame is stored
+        let mut runs = vec![];
+        let mut total :usize = 0;
+        for (line_num, line) in lines{
+            let (keys, count) = line.split_once(' ').ok_or(error(line_num, "expected '<keypad mask> <frames>'"))?;
+            let keys = u16::from_str_radix(keys, 16).map_err(|_| error(line_num, "invalid keypad mask"))?;
+            let count :usize = count.trim().parse().map_err(|_| error(line_num, "invalid frame count"))?;
+            total = total.checked_add(count).filter(|total| *total <= MAX_FRAMES)
+                .ok_or(error(line_num, &format!("movie is longer than {} frames", MAX_FRAMES)))?;
+            runs.push((keys, count));
+        }
+        movie.frames.reserve_exact(total);
+        for (keys, count) in runs{
+            movie.frames.extend(std::iter::repeat_n(keys, count));
+        }
+        Ok(movie)
+    }
+
+    pub fn load(path :&str) -> Result<Movie, String>{
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Movie::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn save(& self, path :&str) -> Result<(), String>{
+        let mut file = fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        self.write(&mut file).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+fn state_sha1(emulator :&Chip8) -> String{
+    let mut state = vec![];
+    //Writing to a Vec can't fail
+    let _ = emulator.save_state(&mut state);
+    sha1_hex(&state)
+}
+
+fn set_keypad(emulator :&mut Chip8, keys :u16){
+    for key in 0..16{
+        emulator.set_key(key, keys >> key & 1 == 1);
+    }
+}
+
+//Records or replays a movie one frame at a time. Frontends call start_frame before
+//running a frame's instructions and end_frame after the timers tick
+pub enum MovieSession{
+    Recording { movie: Movie, path: String },
+    Replaying { movie: Movie, frame: usize, verdict: Option<Result<String, String>> }
+}
+
+impl MovieSession{
+
+    pub fn record(movie :Movie, path :String) -> MovieSession{
+        MovieSession::Recording { movie, path }
+    }
+
+    pub fn replay(movie :Movie) -> MovieSession{
+        MovieSession::Replaying { movie, frame: 0, verdict: None }
+    }
+
+    //Records the keypad, or replaces it with the recorded one. Past the end of a
+    //replay the keypad is left alone so the player can take over
+    pub fn start_frame(&mut self, emulator :&mut Chip8){
+        match self{
+            MovieSession::Recording { movie, .. } => movie.frames.push(emulator.get_keypad()),
+            MovieSession::Replaying { movie, frame, .. } => {
+                if let Some(keys) = movie.frames.get(*frame){
+                    set_keypad(emulator, *keys);
+                }
+            }
+        }
+    }
+
+    //Returns the verification result once, at the end of a replay
+    pub fn end_frame(&mut self, emulator :&Chip8) -> Option<Result<String, String>>{
+        match self{
+            MovieSession::Recording { .. } => None,
+            MovieSession::Replaying { movie, frame, verdict } => {
+                *frame += 1;
+                if *frame != movie.len(){
+                    return None;
+                }
+                let result = match &movie.final_state{
+                    Some(expected) if *expected != state_sha1(emulator) => Err(format!("Replay diverged from the recording after {} frames", frame)),
+                    Some(_) => Ok(format!("Replay finished, {} frames match the recording", frame)),
+                    None => Ok(format!("Replay finished after {} frames", frame))
+                };
+                *verdict = Some(result.clone());
+                Some(result)
+            }
+        }
+    }
+
+    //Writes a recording, or reports how far a replay got. A recording ends with the
+    //state after its last frame, keys pressed since are put back as that frame saw them
+    pub fn finish(self, emulator :&mut Chip8) -> Result<String, String>{
+        match self{
+            MovieSession::Recording { mut movie, path } => {
+                if let Some(keys) = movie.frames.last(){
+                    set_keypad(emulator, *keys);
+                    movie.final_state = Some(state_sha1(emulator));
+                }
+                movie.save(&path)?;
+                Ok(format!("Recorded {} frames to {}", movie.len(), path))
+            },
+            MovieSession::Replaying { verdict: Some(verdict), .. } => verdict,
+            MovieSession::Replaying { movie, frame, .. } => Ok(format!("Replay stopped after {} of {} frames", frame, movie.len()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::audio::BeepRecorder;
+    use crate::chip8::test_support::{pong, PONG};
+    use crate::headless::{Headless, RunLength};
+
+    #[test]
+    fn written_movies_parse_back(){
+        let mut movie = Movie::new(String::from("abc123"), 42, 500, Quirks::from_name("vip").unwrap());
+        movie.frames = vec![0, 0, 0, 0x10, 0x10, 0xFFFF];
+        movie.final_state = Some(String::from("def456"));
+        let mut text = vec![];
+        movie.write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("\n0000 3\n0010 2\nFFFF 1\n"));
+
+        let parsed = Movie::parse(&text).unwrap();
+        assert_eq!(parsed.rom_sha1, movie.rom_sha1);
+        assert_eq!((parsed.seed, parsed.speed), (42, 500));
+        assert_eq!(parsed.quirks, movie.quirks);
+        assert_eq!(parsed.frames, movie.frames);
+        assert_eq!(parsed.final_state, movie.final_state);
+    }
+
+    #[test]
+    fn rejects_malformed_movies(){
+        let valid = "rust-8 movie 1\nrom abc\nseed 1\nspeed 700\nframes\n0000 2\n";
+        assert_eq!(Movie::parse(valid).unwrap().len(), 2);
+        for bad in [
+            "",
+            "rust-8 movie 2\nframes\n",
+            "rust-8 movie 1\nrom abc\n",
+            "rust-8 movie 1\ncolor red\nframes\n",
+            "rust-8 movie 1\nquirks warp_speed=1\nframes\n",
+            "rust-8 movie 1\nframes\nXYZ 2\n",
+            "rust-8 movie 1\nframes\n0000\n"
+        ]{
+            assert!(Movie::parse(bad).is_err(), "{:?}", bad);
+        }
+
+        //Frame counts are checked before anything is allocated
+        let huge = "rust-8 movie 1\nframes\n0000 18446744073709551615\n";
+        assert_eq!(Movie::parse(huge).err().unwrap(), format!("line 3: movie is longer than {} frames", MAX_FRAMES));
+        let overflowing = "rust-8 movie 1\nframes\n0000 1\n0010 18446744073709551615\n";
+        assert!(Movie::parse(overflowing).err().unwrap().starts_with("line 4:"));
+    }
+
+    //Records a run of pong with some key presses and returns the movie
+    fn record(frames :u32) -> Movie{
+        let mut headless = Headless::new(RunLength::Frames(frames));
+        //Pong starts reading the paddle keys after about 80 frames
+        headless.press_key(80, 4);
+        headless.release_key(110, 4);
+        let path = std::env::temp_dir().join(format!("rust8-movie-{}-{}.txt", frames, std::process::id())).to_string_lossy().into_owned();
+        let mut session = MovieSession::record(Movie::new(sha1_hex(PONG), 7, 700, Quirks::default()), path.clone());
+        let mut chip = pong(7);
+        headless.run(&mut chip, &mut BeepRecorder::new(), Some(&mut session), None).unwrap();
+        //A key pressed after the last frame isn't part of the recording
+        chip.set_key(1, true);
+        session.finish(&mut chip).unwrap();
+        let movie = Movie::load(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        movie
+    }
+
+    fn replay(movie :Movie) -> Option<Result<String, String>>{
+        let frames = movie.len() as u32;
+        let mut session = MovieSession::replay(movie);
+        Headless::new(RunLength::Frames(frames)).run(&mut pong(7), &mut BeepRecorder::new(), Some(&mut session), None).unwrap();
+        match session{
+            MovieSession::Replaying { verdict, .. } => verdict,
+            MovieSession::Recording { .. } => unreachable!()
+        }
+    }
+
+    #[test]
+    fn replays_match_the_recording(){
+        let movie = record(120);
+        assert_eq!(movie.len(), 120);
+        assert!(matches!(replay(movie), Some(Ok(_))));
+    }
+
+    #[test]
+    fn replays_with_different_input_diverge(){
+        let mut movie = record(120);
+        //Without the recorded hold of key 4 the paddle doesn't move down
+        for keys in &mut movie.frames[80..110]{
+            *keys &= !(1 << 4);
+        }
+        assert!(matches!(replay(movie), Some(Err(_))));
+    }
+}
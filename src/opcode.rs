@@ -0,0 +1,246 @@
+//Decoded form of every opcode the interpreter understands. Shared by Chip8::cycle,
+//the disassembler and the assembler so they can't disagree on the encoding.
+//x and y are register numbers, nn a byte, nnn a 12 bit address and n a nibble
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction{
+    //0000, treated as a no-op
+    Nop,
+    //00E0
+    ClearScreen,
+    //00EE
+    Return,
+    //SCHIP 00CN
+    ScrollDown { n: u8 },
+    //XO-CHIP 00DN
+    ScrollUp { n: u8 },
+    //SCHIP 00FB
+    ScrollRight,
+    //SCHIP 00FC
+    ScrollLeft,
+    //SCHIP 00FD
+    Exit,
+    //SCHIP 00FE
+    LowRes,
+    //SCHIP 00FF
+    HighRes,
+    //1NNN
+    Jump { nnn: u16 },
+    //2NNN
+    Call { nnn: u16 },
+    //3XNN
+    SkipEqualImmediate { x: usize, nn: u8 },
+    //4XNN
+    SkipNotEqualImmediate { x: usize, nn: u8 },
+    //5XY0
+    SkipEqual { x: usize, y: usize },
+    //XO-CHIP 5XY2
+    SaveRange { x: usize, y: usize },
+    //XO-CHIP 5XY3
+    LoadRange { x: usize, y: usize },
+    //6XNN
+    LoadImmediate { x: usize, nn: u8 },
+    //7XNN
+    AddImmediate { x: usize, nn: u8 },
+    //8XY0
+    Move { x: usize, y: usize },
+    //8XY1
+    Or { x: usize, y: usize },
+    //8XY2
+    And { x: usize, y: usize },
+    //8XY3
+    Xor { x: usize, y: usize },
+    //8XY4
+    Add { x: usize, y: usize },
+    //8XY5
+    Sub { x: usize, y: usize },
+    //8XY6
+    ShiftRight { x: usize, y: usize },
+    //8XY7
+    SubReverse { x: usize, y: usize },
+    //8XYE
+    ShiftLeft { x: usize, y: usize },
+    //9XY0
+    SkipNotEqual { x: usize, y: usize },
+    //ANNN
+    LoadIndex { nnn: u16 },
+    //BNNN, x is only used with the jump_uses_vx quirk
+    JumpOffset { x: usize, nnn: u16 },
+    //CXNN
+    Random { x: usize, nn: u8 },
+    //DXYN
+    Draw { x: usize, y: usize, n: u8 },
+    //EX9E
+    SkipKeyPressed { x: usize },
+    //EXA1
+    SkipKeyNotPressed { x: usize },
+    //XO-CHIP F000 NNNN, the address is the word following the opcode
+    LoadIndexLong,
+    //XO-CHIP FN01
+    SelectPlanes { n: u8 },
+    //XO-CHIP F002
+    LoadAudio,
+    //FX07
+    GetDelay { x: usize },
+    //FX0A
+    WaitKey { x: usize },
+    //FX15
+    SetDelay { x: usize },
+    //FX18
+    SetSound { x: usize },
+    //FX1E
+    AddIndex { x: usize },
+    //FX29
+    FontChar { x: usize },
+    //SCHIP FX30
+    BigFontChar { x: usize },
+    //FX33
+    Bcd { x: usize },
+    //XO-CHIP FX3A
+    SetPitch { x: usize },
+    //FX55
+    Store { x: usize },
+    //FX65
+    Load { x: usize },
+    //SCHIP FX75
+    SaveFlags { x: usize },
+    //SCHIP FX85
+    LoadFlags { x: usize }
+}
+
+impl Instruction{
+
+    pub fn decode(opcode :u16) -> Option<Instruction>{
+        let digit_1 :u16 = (opcode & 0xF000) >> 12;
+        let digit_2 :u16 = (opcode & 0x0F00) >> 8;
+        let digit_3 :u16 = (opcode & 0x00F0) >> 4;
+        let digit_4 :u16 = opcode & 0x000F;
+
+        let x = digit_2 as usize;
+        let y = digit_3 as usize;
+        let n = digit_4 as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        let instruction = match (digit_1, digit_2, digit_3, digit_4){
+            (0,0,0,0) => Instruction::Nop,
+            (0,0,0xE,0) => Instruction::ClearScreen,
+            (0,0,0xE,0xE) => Instruction::Return,
+            (0,0,0xC,_) => Instruction::ScrollDown { n },
+            (0,0,0xD,_) => Instruction::ScrollUp { n },
+            (0,0,0xF,0xB) => Instruction::ScrollRight,
+            (0,0,0xF,0xC) => Instruction::ScrollLeft,
+            (0,0,0xF,0xD) => Instruction::Exit,
+            (0,0,0xF,0xE) => Instruction::LowRes,
+            (0,0,0xF,0xF) => Instruction::HighRes,
+            (1, _, _, _) => Instruction::Jump { nnn },
+            (2, _, _, _) => Instruction::Call { nnn },
+            (3, _, _, _) => Instruction::SkipEqualImmediate { x, nn },
+            (4, _, _, _) => Instruction::SkipNotEqualImmediate { x, nn },
+            (5, _, _, 0) => Instruction::SkipEqual { x, y },
+            (5, _, _, 2) => Instruction::SaveRange { x, y },
+            (5, _, _, 3) => Instruction::LoadRange { x, y },
+            (6, _, _, _) => Instruction::LoadImmediate { x, nn },
+            (7, _, _, _) => Instruction::AddImmediate { x, nn },
+            (8, _, _, 0) => Instruction::Move { x, y },
+            (8, _, _, 1) => Instruction::Or { x, y },
+            (8, _, _, 2) => Instruction::And { x, y },
+            (8, _, _, 3) => Instruction::Xor { x, y },
+            (8, _, _, 4) => Instruction::Add { x, y },
+            (8, _, _, 5) => Instruction::Sub { x, y },
+            (8, _, _, 6) => Instruction::ShiftRight { x, y },
+            (8, _, _, 7) => Instruction::SubReverse { x, y },
+            (8, _, _, 0xE) => Instruction::ShiftLeft { x, y },
+            (9, _, _, 0) => Instruction::SkipNotEqual { x, y },
+            (0xA, _, _, _) => Instruction::LoadIndex { nnn },
+            (0xB, _, _, _) => Instruction::JumpOffset { x, nnn },
+            (0xC, _, _, _) => Instruction::Random { x, nn },
+            (0xD, _, _, _) => Instruction::Draw { x, y, n },
+            (0xE, _, 9, 0xE) => Instruction::SkipKeyPressed { x },
+            (0xE, _, 0xA, 1) => Instruction::SkipKeyNotPressed { x },
+            (0xF, 0, 0, 0) => Instruction::LoadIndexLong,
+            (0xF, _, 0, 1) => Instruction::SelectPlanes { n: digit_2 as u8 },
+            (0xF, 0, 0, 2) => Instruction::LoadAudio,
+            (0xF, _, 0, 7) => Instruction::GetDelay { x },
+            (0xF, _, 0, 0xA) => Instruction::WaitKey { x },
+            (0xF, _, 1, 5) => Instruction::SetDelay { x },
+            (0xF, _, 1, 8) => Instruction::SetSound { x },
+            (0xF, _, 1, 0xE) => Instruction::AddIndex { x },
+            (0xF, _, 2, 9) => Instruction::FontChar { x },
+            (0xF, _, 3, 0) => Instruction::BigFontChar { x },
+            (0xF, _, 3, 3) => Instruction::Bcd { x },
+            (0xF, _, 3, 0xA) => Instruction::SetPitch { x },
+            (0xF, _, 5, 5) => Instruction::Store { x },
+            (0xF, _, 6, 5) => Instruction::Load { x },
+            (0xF, _, 7, 5) => Instruction::SaveFlags { x },
+            (0xF, _, 8, 5) => Instruction::LoadFlags { x },
+            (_, _, _, _) => return None
+        };
+        Some(instruction)
+    }
+
+    //Inverse of decode. For LoadIndexLong this is only the F000 prefix
+    pub fn encode(& self) -> u16{
+        let xy = |x :usize, y :usize| (x as u16) << 8 | (y as u16) << 4;
+        match *self{
+            Instruction::Nop => 0x0000,
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollDown { n } => 0x00C0 | n as u16,
+            Instruction::ScrollUp { n } => 0x00D0 | n as u16,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump { nnn } => 0x1000 | nnn,
+            Instruction::Call { nnn } => 0x2000 | nnn,
+            Instruction::SkipEqualImmediate { x, nn } => 0x3000 | xy(x, 0) | nn as u16,
+            Instruction::SkipNotEqualImmediate { x, nn } => 0x4000 | xy(x, 0) | nn as u16,
+            Instruction::SkipEqual { x, y } => 0x5000 | xy(x, y),
+            Instruction::SaveRange { x, y } => 0x5002 | xy(x, y),
+            Instruction::LoadRange { x, y } => 0x5003 | xy(x, y),
+            Instruction::LoadImmediate { x, nn } => 0x6000 | xy(x, 0) | nn as u16,
+            Instruction::AddImmediate { x, nn } => 0x7000 | xy(x, 0) | nn as u16,
+            Instruction::Move { x, y } => 0x8000 | xy(x, y),
+            Instruction::Or { x, y } => 0x8001 | xy(x, y),
+            Instruction::And { x, y } => 0x8002 | xy(x, y),
+            Instruction::Xor { x, y } => 0x8003 | xy(x, y),
+            Instruction::Add { x, y } => 0x8004 | xy(x, y),
+            Instruction::Sub { x, y } => 0x8005 | xy(x, y),
+            Instruction::ShiftRight { x, y } => 0x8006 | xy(x, y),
+            Instruction::SubReverse { x, y } => 0x8007 | xy(x, y),
+            Instruction::ShiftLeft { x, y } => 0x800E | xy(x, y),
+            Instruction::SkipNotEqual { x, y } => 0x9000 | xy(x, y),
+            Instruction::LoadIndex { nnn } => 0xA000 | nnn,
+            Instruction::JumpOffset { nnn, .. } => 0xB000 | nnn,
+            Instruction::Random { x, nn } => 0xC000 | xy(x, 0) | nn as u16,
+            Instruction::Draw { x, y, n } => 0xD000 | xy(x, y) | n as u16,
+            Instruction::SkipKeyPressed { x } => 0xE09E | xy(x, 0),
+            Instruction::SkipKeyNotPressed { x } => 0xE0A1 | xy(x, 0),
+            Instruction::LoadIndexLong => 0xF000,
+            Instruction::SelectPlanes { n } => 0xF001 | (n as u16) << 8,
+            Instruction::LoadAudio => 0xF002,
+            Instruction::GetDelay { x } => 0xF007 | xy(x, 0),
+            Instruction::WaitKey { x } => 0xF00A | xy(x, 0),
+            Instruction::SetDelay { x } => 0xF015 | xy(x, 0),
+            Instruction::SetSound { x } => 0xF018 | xy(x, 0),
+            Instruction::AddIndex { x } => 0xF01E | xy(x, 0),
+            Instruction::FontChar { x } => 0xF029 | xy(x, 0),
+            Instruction::BigFontChar { x } => 0xF030 | xy(x, 0),
+            Instruction::Bcd { x } => 0xF033 | xy(x, 0),
+            Instruction::SetPitch { x } => 0xF03A | xy(x, 0),
+            Instruction::Store { x } => 0xF055 | xy(x, 0),
+            Instruction::Load { x } => 0xF065 | xy(x, 0),
+            Instruction::SaveFlags { x } => 0xF075 | xy(x, 0),
+            Instruction::LoadFlags { x } => 0xF085 | xy(x, 0)
+        }
+    }
+
+    //Size in bytes, including the address word that follows F000
+    pub fn length(& self) -> u16{
+        match self{
+            Instruction::LoadIndexLong => 4,
+            _ => 2
+        }
+    }
+}
@@ -0,0 +1,106 @@
+pub type Rgb = [u8; 3];
+
+//Colors for each combination of the two XO-CHIP bitplanes: background, plane 1 only,
+//plane 2 only and both. Roms that never select plane 2 only use the first two
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette{
+    pub colors: [Rgb; 4]
+}
+
+//Named palettes in the order the palette hotkey cycles through them
+pub const PRESETS: [(&str, Palette); 5] = [
+    ("classic", Palette { colors: [[0x00,0x00,0x00], [0x00,0xFF,0x66], [0xFF,0x66,0x00], [0xFF,0xFF,0xFF]] }),
+    ("amber", Palette { colors: [[0x1A,0x0E,0x00], [0xFF,0xB0,0x00], [0x99,0x5C,0x00], [0xFF,0xE0,0x99]] }),
+    ("lcd", Palette { colors: [[0x9B,0xBC,0x0F], [0x0F,0x38,0x0F], [0x30,0x62,0x30], [0x8B,0xAC,0x0F]] }),
+    ("grayscale", Palette { colors: [[0x00,0x00,0x00], [0xFF,0xFF,0xFF], [0x80,0x80,0x80], [0xC0,0xC0,0xC0]] }),
+    ("high-contrast", Palette { colors: [[0x00,0x00,0x00], [0xFF,0xFF,0x00], [0x00,0xFF,0xFF], [0xFF,0xFF,0xFF]] })
+];
+
+impl Palette{
+
+    pub fn from_name(name :&str) -> Option<Palette>{
+        let name = name.to_lowercase();
+        PRESETS.iter().find(|(preset, _)| *preset == name).map(|(_, palette)| *palette)
+    }
+
+    //Either a preset name or a comma separated list of 2 or 4 hex colors like
+    //"000000,33ff66". With 2 colors the foreground is used for every plane
+    pub fn parse(text :&str) -> Result<Palette, String>{
+        if let Some(palette) = Palette::from_name(text){
+            return Ok(palette);
+        }
+        if !text.contains(','){
+            return Err(format!("Unknown palette '{}', expected one of {} or 2 or 4 hex colors", text, preset_names().join(", ")));
+        }
+
+        let colors = text.split(',').map(|color| parse_hex(color.trim()).ok_or(format!("Invalid color '{}', expected RRGGBB", color)))
+            .collect::<Result<Vec<Rgb>, String>>()?;
+        match colors[..]{
+            [background, foreground] => Ok(Palette { colors: [background, foreground, foreground, foreground] }),
+            [background, plane_1, plane_2, both] => Ok(Palette { colors: [background, plane_1, plane_2, both] }),
+            _ => Err(format!("Expected 2 or 4 colors in palette '{}'", text))
+        }
+    }
+
+    pub fn color(& self, pixel :u8) -> Rgb{
+        self.colors[(pixel & 0x3) as usize]
+    }
+}
+
+impl Default for Palette{
+    fn default() -> Palette{
+        PRESETS[0].1
+    }
+}
+
+//The preset after palette, for cycling through them with a hotkey. Custom palettes go to the first one
+pub fn next_preset(palette :&Palette) -> (&'static str, Palette){
+    let next = PRESETS.iter().position(|(_, preset)| preset == palette).map_or(0, |index| (index + 1) % PRESETS.len());
+    PRESETS[next]
+}
+
+pub fn preset_names() -> Vec<&'static str>{
+    PRESETS.iter().map(|(name, _)| *name).collect()
+}
+
+//RRGGBB with an optional leading # or 0x
+pub fn parse_hex(text :&str) -> Option<Rgb>{
+    let hex = text.strip_prefix('#').or(text.strip_prefix("0x")).unwrap_or(text);
+    if hex.len() != 6 || !hex.is_ascii(){
+        return None;
+    }
+    let channel = |index :usize| u8::from_str_radix(&hex[index..index+2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn parses_presets_and_custom_colors(){
+        assert_eq!(Palette::parse("LCD"), Ok(PRESETS[2].1));
+        let two = Palette::parse("#000000, 0x33ff66").unwrap();
+        assert_eq!(two.colors, [[0, 0, 0], [0x33, 0xFF, 0x66], [0x33, 0xFF, 0x66], [0x33, 0xFF, 0x66]]);
+        let four = Palette::parse("000000,ff0000,00ff00,0000ff").unwrap();
+        assert_eq!((four.color(1), four.color(2), four.color(3)), ([0xFF, 0, 0], [0, 0xFF, 0], [0, 0, 0xFF]));
+        //Only the two plane bits pick the color
+        assert_eq!(four.color(0x6), four.color(2));
+        for bad in ["sepia", "000000,ff00", "000000,ff0000,00ff00", "000000,gg0000", "000000,ff00é"]{
+            assert!(Palette::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn hotkey_cycles_through_the_presets(){
+        let mut palette = Palette::default();
+        let names :Vec<&str> = (0..PRESETS.len()).map(|_| {
+            let (name, next) = next_preset(&palette);
+            palette = next;
+            name
+        }).collect();
+        assert_eq!(names, ["amber", "lcd", "grayscale", "high-contrast", "classic"]);
+        //A custom palette goes back to the first preset
+        assert_eq!(next_preset(&Palette::parse("123456,abcdef").unwrap()).0, "classic");
+    }
+}
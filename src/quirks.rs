@@ -0,0 +1,107 @@
+//Behaviour of opcodes that differ between CHIP-8 implementations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks{
+    //8XY6/8XYE copy VY into VX before shifting
+    pub shift_uses_vy: bool,
+    //BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    //FX55/FX65 leave I pointing past the last register stored/loaded
+    pub load_store_increments_i: bool,
+    //With load_store_increments_i, I stops at the last register instead of past it (CHIP-48)
+    pub load_store_increments_i_by_x: bool,
+    //DXYN wraps sprites around the screen edges instead of clipping them
+    pub draw_wraps: bool,
+    //8XY1/8XY2/8XY3 reset VF to 0
+    pub logic_resets_vf: bool
+}
+
+impl Quirks{
+
+    pub fn cosmac_vip() -> Quirks{
+        Quirks { shift_uses_vy: true, jump_uses_vx: false, load_store_increments_i: true, load_store_increments_i_by_x: false, draw_wraps: false, logic_resets_vf: true }
+    }
+
+    pub fn chip48() -> Quirks{
+        Quirks { shift_uses_vy: false, jump_uses_vx: true, load_store_increments_i: true, load_store_increments_i_by_x: true, draw_wraps: false, logic_resets_vf: false }
+    }
+
+    pub fn schip() -> Quirks{
+        Quirks { shift_uses_vy: false, jump_uses_vx: true, load_store_increments_i: false, load_store_increments_i_by_x: false, draw_wraps: false, logic_resets_vf: false }
+    }
+
+    pub fn xo_chip() -> Quirks{
+        Quirks { shift_uses_vy: true, jump_uses_vx: false, load_store_increments_i: true, load_store_increments_i_by_x: false, draw_wraps: true, logic_resets_vf: false }
+    }
+
+    //Looks up a preset by name: vip, chip48, schip or xochip
+    pub fn from_name(name :&str) -> Option<Quirks>{
+        match name.to_lowercase().as_str(){
+            "vip" | "cosmac" | "cosmac-vip" => Some(Quirks::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Quirks::chip48()),
+            "schip" | "superchip" => Some(Quirks::schip()),
+            "xochip" | "xo-chip" => Some(Quirks::xo_chip()),
+            _ => None
+        }
+    }
+
+    //Every quirk by name, for files that need to store the exact set
+    pub fn flags(& self) -> [(&'static str, bool); 6]{
+        [
+            ("shift_uses_vy", self.shift_uses_vy),
+            ("jump_uses_vx", self.jump_uses_vx),
+            ("load_store_increments_i", self.load_store_increments_i),
+            ("load_store_increments_i_by_x", self.load_store_increments_i_by_x),
+            ("draw_wraps", self.draw_wraps),
+            ("logic_resets_vf", self.logic_resets_vf)
+        ]
+    }
+
+    //Sets a quirk by the name flags uses, returns false for unknown names
+    pub fn set_flag(&mut self, name :&str, value :bool) -> bool{
+        let flag = match name{
+            "shift_uses_vy" => &mut self.shift_uses_vy,
+            "jump_uses_vx" => &mut self.jump_uses_vx,
+            "load_store_increments_i" => &mut self.load_store_increments_i,
+            "load_store_increments_i_by_x" => &mut self.load_store_increments_i_by_x,
+            "draw_wraps" => &mut self.draw_wraps,
+            "logic_resets_vf" => &mut self.logic_resets_vf,
+            _ => return false
+        };
+        *flag = value;
+        true
+    }
+}
+
+//SCHIP is the default since most of the rom library targets it
+impl Default for Quirks{
+    fn default() -> Quirks{
+        Quirks::schip()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn presets_are_found_by_name(){
+        assert_eq!(Quirks::from_name("VIP"), Some(Quirks::cosmac_vip()));
+        assert_eq!(Quirks::from_name("cosmac-vip"), Some(Quirks::cosmac_vip()));
+        assert_eq!(Quirks::from_name("chip-48"), Some(Quirks::chip48()));
+        assert_eq!(Quirks::from_name("superchip"), Some(Quirks::schip()));
+        assert_eq!(Quirks::from_name("xo-chip"), Some(Quirks::xo_chip()));
+        assert_eq!(Quirks::from_name("chip-9"), None);
+    }
+
+    #[test]
+    fn flags_set_back_by_name(){
+        for preset in [Quirks::cosmac_vip(), Quirks::chip48(), Quirks::schip(), Quirks::xo_chip()]{
+            let mut quirks = Quirks { shift_uses_vy: false, jump_uses_vx: false, load_store_increments_i: false, load_store_increments_i_by_x: false, draw_wraps: false, logic_resets_vf: false };
+            for (name, value) in preset.flags(){
+                assert!(quirks.set_flag(name, value));
+            }
+            assert_eq!(quirks, preset);
+        }
+        assert!(!Quirks::default().set_flag("warp_speed", true));
+    }
+}
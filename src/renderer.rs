@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::io::BufWriter;
+use crate::Chip8;
+use crate::palette::Palette;
+
+
+//One frame of the display: a value per pixel, row by row, bit 0 is plane 1 and bit 1 plane 2
+pub struct Frame<'a>{
+    pub pixels: &'a [u8],
+    pub width: usize,
+    pub height: usize
+}
+
+impl Frame<'_>{
+
+    pub fn of(emulator :&Chip8) -> Frame<'_>{
+        Frame { pixels: emulator.get_display(), width: emulator.display_width(), height: emulator.display_height() }
+    }
+
+    //The frame as RGB bytes, each pixel scaled to a scale x scale block
+    pub fn to_rgb(& self, palette :&Palette, scale :usize) -> Vec<u8>{
+        let mut rgb = Vec::with_capacity(self.width*self.height*scale*scale*3);
+        for row in self.pixels.chunks(self.width){
+            let line :Vec<u8> = row.iter().flat_map(|pixel| palette.color(*pixel).repeat(scale)).collect();
+            for _i in 0..scale{
+                rgb.extend_from_slice(&line);
+            }
+        }
+        rgb
+    }
+}
+
+//Where frames go. The run loop tells a renderer about the resolution before the
+//first frame and whenever the rom switches between low and high-res, then hands
+//it each frame that changed at the end of a 60Hz frame
+pub trait Renderer{
+    fn resize(&mut self, width :usize, height :usize) -> Result<(), String>;
+    fn present(&mut self, frame :&Frame) -> Result<(), String>;
+}
+
+//Feeds a renderer from a run loop, only sending frames when the display changed
+#[derive(Default)]
+pub struct Presenter{
+    //Resolution the renderer was last told about
+    size: Option<(usize, usize)>
+}
+
+impl Presenter{
+
+    pub fn new() -> Presenter{
+        Presenter::default()
+    }
+
+    //Presents the display if it changed since the last call, or anyway if forced
+    //(e.g. the window was uncovered). Returns true if a frame was presented
+    pub fn update(&mut self, emulator :&mut Chip8, renderer :&mut dyn Renderer, force :bool) -> Result<bool, String>{
+        if !emulator.take_display_changed() && !force && self.size.is_some(){
+            return Ok(false);
+        }
+        let size = (emulator.display_width(), emulator.display_height());
+        if self.size != Some(size){
+            renderer.resize(size.0, size.1)?;
+            self.size = Some(size);
+        }
+        renderer.present(&Frame::of(emulator))?;
+        Ok(true)
+    }
+}
+
+//Writes every frame it is given to a PNG file, scale x scale image pixels per CHIP-8 pixel
+pub struct PngRenderer{
+    path: String,
+    palette: Palette,
+    scale: usize
+}
+
+impl PngRenderer{
+
+    pub fn new(path :String, palette :Palette, scale :u32) -> PngRenderer{
+        PngRenderer { path, palette, scale: scale.max(1) as usize }
+    }
+}
+
+impl Renderer for PngRenderer{
+
+    fn resize(&mut self, _width :usize, _height :usize) -> Result<(), String>{
+        Ok(())
+    }
+
+    fn present(&mut self, frame :&Frame) -> Result<(), String>{
+        let error = |e :&dyn std::fmt::Display| format!("Can't write {}: {}", self.path, e);
+        let file = File::create(&self.path).map_err(|e| error(&e))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), (frame.width*self.scale) as u32, (frame.height*self.scale) as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+        writer.write_image_data(&frame.to_rgb(&self.palette, self.scale)).map_err(|e| error(&e))
+    }
+}
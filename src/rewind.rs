@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use crate::Chip8;
+
+
+//Longest history that can be asked for, in seconds
+pub const MAX_REWIND_SECONDS: u32 = 60;
+pub const DEFAULT_REWIND_SECONDS: u32 = 10;
+
+//Ring buffer of the last frames' machine states for rewinding. Only the newest
+//state is kept whole, every older frame is a delta against the frame after it:
+//the XOR of the two states with the runs of unchanged (zero) bytes left out.
+//Memory and the display barely change from one frame to the next, so most deltas
+//are a few dozen bytes
+pub struct Rewind{
+    //Frames of history kept, older ones are dropped
+    capacity: usize,
+    newest: Option<Vec<u8>>,
+    //Oldest first, each one turns a state into the one before it
+    deltas: VecDeque<Vec<u8>>
+}
+
+impl Rewind{
+
+    pub fn new(seconds :u32) -> Rewind{
+        Rewind { capacity: (seconds.min(MAX_REWIND_SECONDS) * 60) as usize, newest: None, deltas: VecDeque::new() }
+    }
+
+    pub fn is_enabled(& self) -> bool{
+        self.capacity > 0
+    }
+
+    //Captures the state at the end of a frame
+    pub fn push(&mut self, emulator :&Chip8){
+        if !self.is_enabled(){
+            return;
+        }
+        let mut state = vec![];
+        //Writing to a Vec can't fail
+        let _ = emulator.save_state(&mut state);
+        if let Some(previous) = self.newest.take(){
+            if self.deltas.len() == self.capacity{
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(encode_delta(&state, &previous));
+        }
+        self.newest = Some(state);
+    }
+
+    //Goes back one frame, returns false once the history runs out
+    pub fn step_back(&mut self, emulator :&mut Chip8) -> bool{
+        let (Some(newest), Some(delta)) = (self.newest.as_mut(), self.deltas.pop_back()) else {
+            return false;
+        };
+        apply_delta(newest, &delta);
+        emulator.load_state(&mut newest.as_slice()).is_ok()
+    }
+
+    //Seconds of history currently available
+    pub fn seconds(& self) -> f32{
+        self.deltas.len() as f32 / 60.0
+    }
+}
+
+//Delta from state to target: target's length, then runs of
+//<unchanged bytes u16> <changed bytes u16> <XOR of the changed bytes>
+fn encode_delta(state :&[u8], target :&[u8]) -> Vec<u8>{
+    let length = state.len().max(target.len());
+    let xor :Vec<u8> = (0..length).map(|i| state.get(i).unwrap_or(&0) ^ target.get(i).unwrap_or(&0)).collect();
+
+    let mut delta = (target.len() as u32).to_be_bytes().to_vec();
+    let mut position = 0;
+    while position < length{
+        let start = position;
+        while position < length && xor[position] == 0 && position - start < u16::MAX as usize{
+            position += 1;
+        }
+        let unchanged = position - start;
+        let changes = position;
+        while position < length && xor[position] != 0 && position - changes < u16::MAX as usize{
+            position += 1;
+        }
+        delta.extend_from_slice(&(unchanged as u16).to_be_bytes());
+        delta.extend_from_slice(&((position - changes) as u16).to_be_bytes());
+        delta.extend_from_slice(&xor[changes..position]);
+    }
+    delta
+}
+
+//Turns state into the target encode_delta was given
+fn apply_delta(state :&mut Vec<u8>, delta :&[u8]){
+    let target_length = u32::from_be_bytes([delta[0], delta[1], delta[2], delta[3]]) as usize;
+    if state.len() < target_length{
+        state.resize(target_length, 0);
+    }
+
+    let mut position = 0;
+    let mut rest = &delta[4..];
+    while rest.len() >= 4{
+        let unchanged = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let changed = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        position += unchanged;
+        for (byte, xor) in state[position..].iter_mut().zip(&rest[4..4 + changed]){
+            *byte ^= xor;
+        }
+        position += changed;
+        rest = &rest[4 + changed..];
+    }
+    state.truncate(target_length);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::chip8::test_support::{program, state};
+
+    #[test]
+    fn deltas_rebuild_the_target(){
+        let mut long = vec![0; 70000];
+        long[69999] = 7;
+        let cases :[(Vec<u8>, Vec<u8>); 5] = [
+            (vec![1, 2, 3, 4], vec![1, 2, 3, 4]),
+            (vec![1, 2, 3, 4], vec![1, 9, 3, 8]),
+            //The state grows and shrinks when the rom switches resolution
+            (vec![1, 2, 3], vec![1, 2, 3, 4, 5, 6]),
+            (vec![1, 2, 3, 4, 5, 6], vec![6, 2]),
+            //Runs longer than a u16 are split
+            (vec![0; 70000], long)
+        ];
+        for (state, target) in cases{
+            let mut rebuilt = state.clone();
+            apply_delta(&mut rebuilt, &encode_delta(&state, &target));
+            assert_eq!(rebuilt, target);
+        }
+    }
+
+    #[test]
+    fn steps_back_through_resolution_changes(){
+        //LD V0, 1; HIGH; LD V0, 2; LOW; LD V0, 3; JP 0x20A
+        let rom = [0x60, 0x01, 0x00, 0xFF, 0x60, 0x02, 0x00, 0xFE, 0x60, 0x03, 0x12, 0x0A];
+        let mut chip = program(&rom);
+        let mut rewind = Rewind::new(1);
+        let mut states = vec![state(&chip)];
+        let mut widths = vec![chip.display_width()];
+        rewind.push(&chip);
+        for _frame in 0..6{
+            chip.cycle().unwrap();
+            states.push(state(&chip));
+            widths.push(chip.display_width());
+            rewind.push(&chip);
+        }
+        assert!(widths.contains(&128) && widths.contains(&64));
+
+        states.pop();
+        widths.pop();
+        while let (Some(expected), Some(width)) = (states.pop(), widths.pop()){
+            assert!(rewind.step_back(&mut chip));
+            assert_eq!(state(&chip), expected);
+            assert_eq!(chip.display_width(), width);
+        }
+        assert!(!rewind.step_back(&mut chip));
+    }
+
+    #[test]
+    fn drops_history_beyond_capacity(){
+        let mut chip = program(&[0x70, 0x01, 0x12, 0x00]);
+        let mut rewind = Rewind::new(1);
+        for _frame in 0..100{
+            chip.cycle().unwrap();
+            rewind.push(&chip);
+        }
+        assert_eq!(rewind.seconds(), 1.0);
+        assert!(!Rewind::new(0).is_enabled());
+    }
+}
@@ -0,0 +1,51 @@
+//Random number source for CXNN. Chip8 owns one so runs can be made reproducible,
+//and its state goes into save states so a loaded state continues the same sequence.
+//Send so a Chip8 can be moved to another thread
+pub trait Rng: Send{
+    fn next_byte(&mut self) -> u8;
+    //Snapshot of the generator for save states, at most 255 bytes
+    fn save(& self) -> Vec<u8>;
+    //Restores a snapshot from save, returns false if it isn't valid for this generator
+    fn restore(&mut self, state :&[u8]) -> bool;
+}
+
+//xorshift64*, small and fast. The state is never zero
+pub struct XorShift{
+    state: u64
+}
+
+impl XorShift{
+
+    //Any seed is valid, it is scrambled (splitmix64) so similar seeds still
+    //give unrelated sequences
+    pub fn new(seed :u64) -> XorShift{
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        XorShift { state: (z ^ (z >> 31)) | 1 }
+    }
+}
+
+impl Rng for XorShift{
+
+    fn next_byte(&mut self) -> u8{
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+    }
+
+    fn save(& self) -> Vec<u8>{
+        self.state.to_be_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state :&[u8]) -> bool{
+        match <[u8; 8]>::try_from(state).map(u64::from_be_bytes){
+            Ok(state) if state != 0 => {
+                self.state = state;
+                true
+            },
+            _ => false
+        }
+    }
+}
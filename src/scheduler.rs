@@ -0,0 +1,205 @@
+use std::time::Duration;
+use crate::Chip8;
+use crate::audio::AudioOutput;
+use crate::capture::VideoRecorder;
+use crate::error::Chip8Error;
+use crate::movie::MovieSession;
+use crate::renderer::Frame;
+
+
+const FRAMES_PER_SECOND: u32 = 60;
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+//Frames run at once after a stall before the rest of the lag is dropped
+const MAX_CATCH_UP: u32 = 5;
+
+//Splits time into 60Hz frames. Each frame runs the instructions due at the
+//configured rate and then ticks the delay and sound timers once, so timers stay
+//at exactly 60Hz whatever the instruction rate is. Frontends feed it wall-clock
+//time with advance, the headless runner just runs frames back to back
+pub struct Scheduler{
+    instructions_per_second: u32,
+    //Wall-clock time not yet turned into frames, in 1/60 nanoseconds so
+    //frames are exactly 1/60s long with no rounding drift
+    pending_time: u128,
+    //Instructions owed times 60, carries the remainder when the rate isn't a multiple of 60
+    instruction_credit: u32
+}
+
+impl Scheduler{
+
+    pub fn new(instructions_per_second :u32) -> Scheduler{
+        Scheduler {
+            instructions_per_second,
+            pending_time: 0,
+            instruction_credit: 0
+        }
+    }
+
+    //Adds elapsed wall-clock time and returns how many frames are due. If the
+    //frontend fell behind by more than the catch-up limit the extra time is dropped
+    pub fn advance(&mut self, elapsed :Duration) -> u32{
+        self.pending_time += elapsed.as_nanos() * FRAMES_PER_SECOND as u128;
+        let due = self.pending_time / NANOS_PER_SECOND;
+        if due > MAX_CATCH_UP as u128{
+            self.pending_time %= NANOS_PER_SECOND;
+            return MAX_CATCH_UP;
+        }
+        self.pending_time -= due * NANOS_PER_SECOND;
+        due as u32
+    }
+
+    //Time left before advance will return another frame
+    pub fn time_until_next_frame(& self) -> Duration{
+        let remaining = NANOS_PER_SECOND - self.pending_time.min(NANOS_PER_SECOND);
+        Duration::from_nanos(remaining.div_ceil(FRAMES_PER_SECOND as u128) as u64)
+    }
+
+    //Number of instructions the next frame will run, without taking them
+    pub fn peek_frame_instructions(& self) -> u32{
+        (self.instruction_credit + self.instructions_per_second) / FRAMES_PER_SECOND
+    }
+
+    //Number of instructions to run in the next frame
+    pub fn next_frame_instructions(&mut self) -> u32{
+        self.instruction_credit += self.instructions_per_second;
+        let instructions = self.instruction_credit / FRAMES_PER_SECOND;
+        self.instruction_credit %= FRAMES_PER_SECOND;
+        instructions
+    }
+
+    //Ends a frame: hands the sound state to the audio output and ticks the timers
+    pub fn end_frame(&mut self, emulator :&mut Chip8, audio :&mut dyn AudioOutput){
+        audio.update(emulator);
+        emulator.decrement_timers();
+    }
+
+    //Runs one whole frame, stopping early if the rom exits or crashes
+    pub fn run_frame(&mut self, emulator :&mut Chip8, audio :&mut dyn AudioOutput) -> Result<(), Chip8Error>{
+        for _i in 0..self.next_frame_instructions(){
+            if emulator.is_halted(){
+                break;
+            }
+            emulator.cycle()?;
+        }
+        self.end_frame(emulator, audio);
+        Ok(())
+    }
+
+    //The frame every frontend runs. A movie session records or replays the keypad
+    //before the frame and checks the state after it, a video recorder gets the
+    //display whatever happened, the caller finishes both. A crashed rom doesn't
+    //run but its timers still run down so the beep stops. Returns the replay's
+    //verdict at the frame it ends
+    pub fn step(&mut self, emulator :&mut Chip8, audio :&mut dyn AudioOutput, crashed :bool, movie :Option<&mut MovieSession>, video :Option<&mut VideoRecorder>) -> Result<Option<Result<String, String>>, Chip8Error>{
+        let result = if crashed{
+            self.end_frame(emulator, audio);
+            Ok(None)
+        }
+        else if let Some(session) = movie{
+            session.start_frame(emulator);
+            self.run_frame(emulator, audio).map(|()| session.end_frame(emulator))
+        }
+        else{
+            self.run_frame(emulator, audio).map(|()| None)
+        };
+        if let Some(recorder) = video{
+            recorder.record(&Frame::of(emulator));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::audio::BeepRecorder;
+    use crate::chip8::test_support::program;
+    use crate::movie::Movie;
+    use crate::palette::Palette;
+
+    fn recorder(name :&str) -> (VideoRecorder, std::path::PathBuf){
+        let path = std::env::temp_dir().join(format!("rust8-step-{}-{}.gif", name, std::process::id()));
+        (VideoRecorder::create(&path.to_string_lossy(), Palette::default(), 2).unwrap(), path)
+    }
+
+    #[test]
+    fn turns_elapsed_time_into_frames(){
+        let mut scheduler = Scheduler::new(700);
+        assert_eq!(scheduler.advance(Duration::from_millis(16)), 0);
+        //The 16ms carry over, so 1ms more completes the frame
+        assert_eq!(scheduler.advance(Duration::from_millis(1)), 1);
+        assert_eq!(scheduler.advance(Duration::from_millis(50)), 3);
+    }
+
+    #[test]
+    fn drops_lag_beyond_the_catch_up_limit(){
+        let mut scheduler = Scheduler::new(700);
+        assert_eq!(scheduler.advance(Duration::from_secs(2)), MAX_CATCH_UP);
+        //Nothing of the stall is left over, only the part of a frame it ended in
+        assert_eq!(scheduler.advance(Duration::ZERO), 0);
+        assert_eq!(scheduler.advance(Duration::from_millis(1010)), MAX_CATCH_UP);
+        assert_eq!(scheduler.advance(Duration::from_millis(7)), 1);
+    }
+
+    #[test]
+    fn reports_the_time_until_the_next_frame(){
+        let mut scheduler = Scheduler::new(700);
+        assert_eq!(scheduler.time_until_next_frame(), Duration::from_nanos(16_666_667));
+        scheduler.advance(Duration::from_millis(10));
+        assert_eq!(scheduler.time_until_next_frame(), Duration::from_nanos(6_666_667));
+        scheduler.advance(Duration::from_nanos(6_666_667));
+        assert_eq!(scheduler.time_until_next_frame(), Duration::from_nanos(16_666_667));
+    }
+
+    #[test]
+    fn carries_the_instruction_remainder(){
+        let mut scheduler = Scheduler::new(700);
+        let frames :Vec<u32> = (0..60).map(|_| scheduler.next_frame_instructions()).collect();
+        assert_eq!(frames.iter().sum::<u32>(), 700);
+        assert!(frames.iter().all(|instructions| *instructions == 11 || *instructions == 12));
+        //Peeking doesn't use up the credit
+        assert_eq!(Scheduler::new(30).peek_frame_instructions(), 0);
+        let mut scheduler = Scheduler::new(90);
+        assert_eq!((scheduler.peek_frame_instructions(), scheduler.next_frame_instructions()), (1, 1));
+        assert_eq!((scheduler.peek_frame_instructions(), scheduler.next_frame_instructions()), (2, 2));
+    }
+
+    #[test]
+    fn steps_replay_movies_and_record_every_frame(){
+        //JP 0x200
+        let mut chip = program(&[0x12, 0x00]);
+        let mut audio = BeepRecorder::new();
+        let mut session = MovieSession::replay(Movie::parse("rust-8 movie 1\nframes\n0010 1\n0000 1\n").unwrap());
+        let (mut video, path) = recorder("movie");
+        let mut scheduler = Scheduler::new(700);
+        assert_eq!(scheduler.step(&mut chip, &mut audio, false, Some(&mut session), Some(&mut video)), Ok(None));
+        assert_eq!(chip.get_keypad(), 0x10);
+        let verdict = scheduler.step(&mut chip, &mut audio, false, Some(&mut session), Some(&mut video));
+        assert_eq!(verdict, Ok(Some(Ok(String::from("Replay finished after 2 frames")))));
+        assert_eq!(chip.get_keypad(), 0);
+        //Past the end the movie has nothing more to say
+        assert_eq!(scheduler.step(&mut chip, &mut audio, false, Some(&mut session), Some(&mut video)), Ok(None));
+        assert!(video.finish().unwrap().starts_with("Recorded 3 frames"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn crashed_steps_only_run_the_timers_down(){
+        //LD V0, 10; LD DT, V0; 5121 isn't an instruction
+        let mut chip = program(&[0x60, 0x0A, 0xF0, 0x15, 0x51, 0x21]);
+        let mut audio = BeepRecorder::new();
+        let (mut video, path) = recorder("crash");
+        let mut scheduler = Scheduler::new(3*60);
+        let crash = scheduler.step(&mut chip, &mut audio, false, None, Some(&mut video));
+        assert_eq!(crash, Err(Chip8Error::InvalidOpcode { pc: 0x204, opcode: 0x5121 }));
+        //The frame ended at the crash, before its timer tick
+        assert_eq!(chip.get_delay_timer(), 10);
+
+        let pc = chip.get_pc();
+        assert_eq!(scheduler.step(&mut chip, &mut audio, true, None, Some(&mut video)), Ok(None));
+        assert_eq!((chip.get_pc(), chip.get_delay_timer()), (pc, 9));
+        //The crash and the frame after it are both in the video
+        assert!(video.finish().unwrap().starts_with("Recorded 2 frames"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
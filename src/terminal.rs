@@ -0,0 +1,270 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use crossterm::{cursor, event, queue, style, terminal};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags};
+use rust8::Chip8;
+use rust8::audio::{AudioSettings, Synth};
+use rust8::capture::{self, VideoRecorder};
+use rust8::error::Chip8Error;
+use rust8::keymap::KeyMap;
+use rust8::movie::MovieSession;
+use rust8::palette::{self, Palette};
+use rust8::renderer::{Frame, Presenter, Renderer};
+use rust8::scheduler::Scheduler;
+
+
+//Most terminals only report key presses, repeats included, never releases. Without
+//releases a key counts as held for this many frames after a press, long enough to
+//bridge the delay before the terminal starts repeating, and a bit after each repeat
+const PRESS_HOLD_FRAMES: u32 = 30;
+const REPEAT_HOLD_FRAMES: u32 = 6;
+
+//Puts the terminal in raw mode on the alternate screen and restores it when
+//dropped, also when the loop returns early with an error
+struct RawTerminal{
+    key_releases: bool
+}
+
+impl RawTerminal{
+
+    fn enter() -> io::Result<RawTerminal>{
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        crossterm::execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+        //Terminals that support the kitty keyboard protocol can report releases
+        let key_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if key_releases{
+            crossterm::execute!(stdout, event::PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(RawTerminal { key_releases })
+    }
+}
+
+impl Drop for RawTerminal{
+    fn drop(&mut self){
+        let mut stdout = io::stdout();
+        if self.key_releases{
+            let _ = crossterm::execute!(stdout, event::PopKeyboardEnhancementFlags);
+        }
+        let _ = crossterm::execute!(stdout, style::ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+//Key names in the form the window reports them, so key maps work in both. Terminals
+//don't report scancodes, so letters are the characters the layout types and not positions
+fn key_name(code :KeyCode) -> Option<String>{
+    match code{
+        KeyCode::Char(' ') => Some(String::from("Space")),
+        KeyCode::Char(c) => Some(c.to_string()),
+        KeyCode::Up => Some(String::from("Up")),
+        KeyCode::Down => Some(String::from("Down")),
+        KeyCode::Left => Some(String::from("Left")),
+        KeyCode::Right => Some(String::from("Right")),
+        KeyCode::Enter => Some(String::from("Return")),
+        KeyCode::Tab => Some(String::from("Tab")),
+        KeyCode::Backspace => Some(String::from("Backspace")),
+        _ => None
+    }
+}
+
+//Each character cell is an upper half block with the top pixel as the foreground
+//color and the bottom pixel as the background, so 64x32 takes 64x16 cells (128x32
+//in high-res). The status line goes below the display
+struct TerminalRenderer{
+    out: io::Stdout,
+    palette: Palette,
+    status: String
+}
+
+impl TerminalRenderer{
+
+    //Every cell is drawn over on each frame, the screen only needs clearing when its size changes
+    fn clear(&mut self) -> Result<(), String>{
+        queue!(self.out, terminal::Clear(terminal::ClearType::All)).map_err(|e| e.to_string())
+    }
+
+    fn draw(&mut self, frame :&Frame) -> io::Result<()>{
+        let (width, height) = (frame.width, frame.height);
+        let (columns, rows) = terminal::size()?;
+        if (columns as usize) < width || (rows as usize) < height/2 + 1{
+            queue!(self.out, style::ResetColor, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0),
+                style::Print(format!("Terminal too small, {}x{} needed", width, height/2 + 1)))?;
+            return self.out.flush();
+        }
+
+        let palette = self.palette;
+        let color = |pixel :u8| {
+            let [r, g, b] = palette.color(pixel);
+            style::Color::Rgb { r, g, b }
+        };
+        for row in 0..height/2{
+            queue!(self.out, cursor::MoveTo(0, row as u16))?;
+            let mut current = None;
+            for col in 0..width{
+                let cell = (frame.pixels[col + width*row*2], frame.pixels[col + width*(row*2 + 1)]);
+                if current != Some(cell){
+                    queue!(self.out, style::SetForegroundColor(color(cell.0)), style::SetBackgroundColor(color(cell.1)))?;
+                    current = Some(cell);
+                }
+                queue!(self.out, style::Print('▀'))?;
+            }
+        }
+        queue!(self.out, style::ResetColor, cursor::MoveTo(0, (height/2) as u16), style::Print(&self.status), terminal::Clear(terminal::ClearType::UntilNewLine))?;
+        self.out.flush()
+    }
+}
+
+impl Renderer for TerminalRenderer{
+
+    fn resize(&mut self, _width :usize, _height :usize) -> Result<(), String>{
+        self.clear()
+    }
+
+    fn present(&mut self, frame :&Frame) -> Result<(), String>{
+        self.draw(frame).map_err(|e| e.to_string())
+    }
+}
+
+//Runs a rom in the terminal instead of a window
+pub struct Terminal{
+    title :String,
+    palette :Palette,
+    key_map :KeyMap,
+    //Screenshots are written as <prefix>-<n>.png at this scale
+    screenshot_prefix :String,
+    screenshot_scale :u32
+}
+
+impl Terminal{
+
+    pub fn new(title :String) -> Terminal{
+        Terminal { title, palette: Palette::default(), key_map: KeyMap::default(), screenshot_prefix: String::from("screenshots/RUST-8"), screenshot_scale: 25 }
+    }
+
+    pub fn set_screenshots(&mut self, prefix :String, scale :u32){
+        self.screenshot_prefix = prefix;
+        self.screenshot_scale = scale;
+    }
+
+    pub fn set_palette(&mut self, palette :Palette){
+        self.palette = palette;
+    }
+
+    pub fn set_key_map(&mut self, key_map :KeyMap){
+        self.key_map = key_map;
+    }
+
+    //Runs until the rom exits or Escape is pressed, frames go through Scheduler::step
+    pub fn terminal_loop(&self, emulator :&mut Chip8, mut movie :Option<&mut MovieSession>, mut video :Option<&mut VideoRecorder>) -> Result<(), String>{
+        let raw = RawTerminal::enter().map_err(|e| e.to_string())?;
+        let mut renderer = TerminalRenderer { out: io::stdout(), palette: self.palette, status: String::new() };
+        let mut presenter = Presenter::new();
+
+        let mut scheduler = Scheduler::new(emulator.get_cycle_speed().max(1) as u32);
+        //Sound isn't played, the status line shows the beep instead
+        let mut audio = Synth::new(AudioSettings::default(), 44100.0);
+        let mut last_time = Instant::now();
+        let mut crash :Option<Chip8Error> = None;
+        let mut frame :u32 = 0;
+        //Frame each keypad key is released at when the terminal doesn't report releases
+        let mut release_at :[Option<u32>; 16] = [None; 16];
+        let mut message = String::new();
+        let mut beeping = false;
+        let mut redraw = true;
+
+        loop{
+            if emulator.is_halted(){
+                break;
+            }
+
+            let now = Instant::now();
+            let frames = scheduler.advance(now - last_time);
+            last_time = now;
+            for _i in 0..frames{
+                frame += 1;
+                for (key, release) in release_at.iter_mut().enumerate(){
+                    if release.is_some_and(|release| release <= frame){
+                        emulator.set_key(key as u8, false);
+                        *release = None;
+                    }
+                }
+                match scheduler.step(emulator, &mut audio, crash.is_some(), movie.as_deref_mut(), video.as_deref_mut()){
+                    Err(error) => {
+                        message = format!("crashed: {}", error);
+                        crash = Some(error);
+                        redraw = true;
+                    },
+                    Ok(Some(Ok(verdict) | Err(verdict))) => {
+                        message = verdict;
+                        redraw = true;
+                    },
+                    Ok(None) => ()
+                }
+            }
+
+            if beeping != (emulator.get_sound_timer() > 0){
+                beeping = !beeping;
+                redraw = true;
+            }
+            if redraw{
+                renderer.status = format!("{} | Esc quits, P palette, F12 screenshot{}{}{}", self.title, if beeping {" | ♪"} else {""},
+                    if message.is_empty() {""} else {" | "}, message);
+            }
+            presenter.update(emulator, &mut renderer, redraw)?;
+            redraw = false;
+
+            //Waiting for input also paces the loop until the next frame is due
+            if !event::poll(scheduler.time_until_next_frame()).map_err(|e| e.to_string())?{
+                continue;
+            }
+            while event::poll(Duration::ZERO).map_err(|e| e.to_string())?{
+                match event::read().map_err(|e| e.to_string())?{
+                    Event::Resize(..) => {
+                        renderer.clear()?;
+                        redraw = true;
+                    },
+                    Event::Key(KeyEvent { code: KeyCode::Esc, kind: KeyEventKind::Press, .. }) => return Ok(()),
+                    Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers, kind: KeyEventKind::Press, .. }) if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                    Event::Key(KeyEvent { code: KeyCode::Char('p' | 'P'), kind: KeyEventKind::Press, .. }) if self.key_map.get("p").is_none() => {
+                        let (name, next) = palette::next_preset(&renderer.palette);
+                        renderer.palette = next;
+                        if let Some(recorder) = video.as_deref_mut(){
+                            recorder.set_palette(next);
+                        }
+                        message = format!("palette {}", name);
+                        redraw = true;
+                    },
+                    Event::Key(KeyEvent { code: KeyCode::F(12), kind: KeyEventKind::Press, .. }) => {
+                        message = match capture::numbered_screenshot(emulator, &self.screenshot_prefix, renderer.palette, self.screenshot_scale){
+                            Ok(path) => format!("saved {}", path),
+                            Err(error) => error
+                        };
+                        redraw = true;
+                    },
+                    Event::Key(KeyEvent { code, kind, .. }) => {
+                        let Some(key) = key_name(code).and_then(|name| self.key_map.get(&name)) else {
+                            continue;
+                        };
+                        match kind{
+                            KeyEventKind::Release => emulator.set_key(key, false),
+                            _ => {
+                                emulator.set_key(key, true);
+                                //Without releases repeats also arrive as presses, a key that is still held is repeating
+                                if !raw.key_releases{
+                                    let release = &mut release_at[key as usize];
+                                    *release = Some(match release{
+                                        Some(release) => (*release).max(frame + REPEAT_HOLD_FRAMES),
+                                        None => frame + PRESS_HOLD_FRAMES
+                                    });
+                                }
+                            }
+                        }
+                    },
+                    _ => ()
+                }
+            }
+        }
+        Ok(())
+    }
+}
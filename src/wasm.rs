@@ -0,0 +1,132 @@
+use wasm_bindgen::prelude::*;
+use crate::Chip8;
+use crate::audio::{AudioSettings, Synth};
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+use crate::renderer::{Frame, Presenter, Renderer};
+use crate::scheduler::Scheduler;
+
+
+//Keeps the last frame as RGBA for the page to copy onto a canvas
+struct RgbaRenderer{
+    palette: Palette,
+    framebuffer: Vec<u8>
+}
+
+impl Renderer for RgbaRenderer{
+
+    fn resize(&mut self, width :usize, height :usize) -> Result<(), String>{
+        self.framebuffer = Vec::with_capacity(width*height*4);
+        Ok(())
+    }
+
+    fn present(&mut self, frame :&Frame) -> Result<(), String>{
+        self.framebuffer.clear();
+        for pixel in frame.pixels{
+            let [r, g, b] = self.palette.color(*pixel);
+            self.framebuffer.extend_from_slice(&[r, g, b, 255]);
+        }
+        Ok(())
+    }
+}
+
+//Browser frontend, exported to JavaScript as Chip8. The page calls run_frame 60
+//times a second and copies the RGBA framebuffer straight out of wasm memory onto a
+//canvas; web/index.html is a complete example
+#[wasm_bindgen(js_name = Chip8)]
+pub struct WebChip8{
+    chip: Chip8,
+    speed: u32,
+    quirks: Quirks,
+    scheduler: Scheduler,
+    synth: Synth,
+    presenter: Presenter,
+    renderer: RgbaRenderer
+}
+
+#[wasm_bindgen(js_class = Chip8)]
+impl WebChip8{
+
+    //speed in instructions per second, quirks one of vip, chip48, schip or xochip
+    #[wasm_bindgen(constructor)]
+    pub fn new(speed :u32, quirks :&str) -> Result<WebChip8, JsError>{
+        let quirks = Quirks::from_name(quirks).ok_or(JsError::new(&format!("Unknown quirks preset '{}'", quirks)))?;
+        let speed = speed.clamp(1, i32::MAX as u32);
+        let mut web = WebChip8 {
+            chip: Chip8::new(speed as i32, quirks),
+            speed,
+            quirks,
+            scheduler: Scheduler::new(speed),
+            synth: Synth::new(AudioSettings::default(), 44100.0),
+            presenter: Presenter::new(),
+            renderer: RgbaRenderer { palette: Palette::default(), framebuffer: vec![] }
+        };
+        web.chip.load_font();
+        web.render(true);
+        Ok(web)
+    }
+
+    //Resets the machine and loads a rom, e.g. the bytes of a fetched file
+    pub fn load_rom(&mut self, rom :&[u8]) -> Result<(), JsError>{
+        let mut chip = Chip8::new(self.speed as i32, self.quirks);
+        chip.load_rom_bytes(rom).map_err(|e| JsError::new(&e.to_string()))?;
+        chip.load_font();
+        self.chip = chip;
+        self.scheduler = Scheduler::new(self.speed);
+        self.render(true);
+        Ok(())
+    }
+
+    //Runs one 60Hz frame. Returns true if the framebuffer changed and needs drawing
+    pub fn run_frame(&mut self) -> Result<bool, JsError>{
+        if !self.chip.is_halted(){
+            self.scheduler.run_frame(&mut self.chip, &mut self.synth).map_err(|e| JsError::new(&e.to_string()))?;
+        }
+        Ok(self.render(false))
+    }
+
+    //key is the keypad key, 0-F
+    pub fn set_key(&mut self, key :u8, pressed :bool){
+        self.chip.set_key(key, pressed);
+    }
+
+    //Start of the RGBA framebuffer in wasm memory. It moves when the resolution
+    //changes, so read it again after every frame that changed the display
+    pub fn framebuffer_ptr(& self) -> *const u8{
+        self.renderer.framebuffer.as_ptr()
+    }
+
+    pub fn width(& self) -> usize{
+        self.chip.display_width()
+    }
+
+    pub fn height(& self) -> usize{
+        self.chip.display_height()
+    }
+
+    pub fn is_halted(& self) -> bool{
+        self.chip.is_halted()
+    }
+
+    //A preset name or hex colors, as for --palette
+    pub fn set_palette(&mut self, palette :&str) -> Result<(), JsError>{
+        self.renderer.palette = Palette::parse(palette).map_err(|e| JsError::new(&e))?;
+        self.render(true);
+        Ok(())
+    }
+
+    //The rate of the page's AudioContext, the beep is generated at this rate
+    pub fn set_sample_rate(&mut self, sample_rate :f32){
+        self.synth = Synth::new(AudioSettings::default(), sample_rate);
+    }
+
+    //Fills an audio buffer with the beep, for a ScriptProcessorNode or AudioWorklet
+    pub fn fill_audio(&mut self, out :&mut [f32]){
+        self.synth.fill(out);
+    }
+
+    //Returns true if the framebuffer was redrawn. The RGBA renderer can't fail
+    fn render(&mut self, force :bool) -> bool{
+        self.presenter.update(&mut self.chip, &mut self.renderer, force).unwrap_or(false)
+    }
+}
@@ -0,0 +1,56 @@
+//Drives the binary the way a CI job would: a --headless run dumps the framebuffer
+//and 'rust-8 test' checks a later run against that dump
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const PONG: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/roms/pong.rom");
+
+//Scratch directory that is also the working directory and config home, so neither
+//the project's nor the user's config changes the runs
+fn scratch(name :&str) -> PathBuf{
+    let directory = std::env::temp_dir().join(format!("rust8-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn rust8(directory :&Path, args :&[&str]) -> Output{
+    Command::new(env!("CARGO_BIN_EXE_RUST-8")).args(args).current_dir(directory)
+        .env("XDG_CONFIG_HOME", directory).output().unwrap()
+}
+
+#[test]
+fn headless_runs_print_only_the_framebuffer(){
+    let directory = scratch("headless");
+    let dumped = rust8(&directory, &[PONG, "--frames", "120", "--seed", "1", "--dump", "pong.txt"]);
+    assert!(dumped.status.success(), "{}", String::from_utf8_lossy(&dumped.stderr));
+    let dump = std::fs::read_to_string(directory.join("pong.txt")).unwrap();
+    assert!(dump.contains('*'));
+
+    //Without --dump the same framebuffer goes to stdout, with nothing else mixed in,
+    //the reports of everything saved along the way go to stderr
+    let printed = rust8(&directory, &[PONG, "--frames", "120", "--seed", "1", "--record", "pong.movie", "--screenshot", "pong.png", "--video", "pong.gif"]);
+    assert!(printed.status.success(), "{}", String::from_utf8_lossy(&printed.stderr));
+    assert_eq!(String::from_utf8_lossy(&printed.stdout), dump);
+    let reports = String::from_utf8_lossy(&printed.stderr);
+    assert!(reports.contains("pong.movie") && reports.contains("pong.png") && reports.contains("pong.gif"), "{}", reports);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn test_passes_on_the_same_run_and_fails_on_another(){
+    let directory = scratch("test");
+    std::fs::write(directory.join("input.txt"), "80 4 down\n110 4 up\n").unwrap();
+    let run = ["--frames", "150", "--seed", "7", "--input", "input.txt"];
+    let record = rust8(&directory, &[&[PONG][..], &run, &["--dump", "expected.txt"]].concat());
+    assert!(record.status.success(), "{}", String::from_utf8_lossy(&record.stderr));
+
+    let pass = rust8(&directory, &[&["test", PONG][..], &run, &["--expect", "expected.txt"]].concat());
+    assert!(pass.status.success(), "{}", String::from_utf8_lossy(&pass.stderr));
+    assert_eq!(String::from_utf8_lossy(&pass.stdout).trim(), format!("PASS {}", PONG));
+
+    //Without the key presses the paddle stays where it was
+    let fail = rust8(&directory, &["test", PONG, "--frames", "150", "--seed", "7", "--expect", "expected.txt"]);
+    assert!(!fail.status.success());
+    assert!(String::from_utf8_lossy(&fail.stderr).contains("FAIL"));
+    std::fs::remove_dir_all(directory).unwrap();
+}
@@ -0,0 +1,31 @@
+//Runs in a headless wasm runner: cargo test --target wasm32-unknown-unknown --no-default-features --features wasm
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use wasm_bindgen_test::*;
+use rust8::wasm::WebChip8;
+
+const PONG: &[u8] = include_bytes!("../roms/pong.rom");
+
+#[wasm_bindgen_test]
+fn runs_a_rom_and_draws(){
+    let mut chip = WebChip8::new(700, "schip").unwrap();
+    chip.load_rom(PONG).unwrap();
+    let mut changed = false;
+    for _frame in 0..60{
+        changed |= chip.run_frame().unwrap();
+    }
+    assert!(changed);
+    assert_eq!((chip.width(), chip.height()), (64, 32));
+
+    //Lit pixels use the classic palette's foreground, unlit ones its background
+    let framebuffer = unsafe { std::slice::from_raw_parts(chip.framebuffer_ptr(), 64*32*4) };
+    assert!(framebuffer.chunks(4).any(|pixel| pixel != framebuffer[0..4].as_ref()));
+}
+
+#[wasm_bindgen_test]
+fn rejects_oversized_roms(){
+    let mut chip = WebChip8::new(700, "schip").unwrap();
+    //Roms can fill memory from 0x200 up to the 64K XO-CHIP limit
+    assert!(chip.load_rom(&vec![0; 0x10000 - 0x200]).is_ok());
+    assert!(chip.load_rom(&vec![0; 0x10000 - 0x200 + 1]).is_err());
+}